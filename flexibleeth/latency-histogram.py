@@ -7,7 +7,10 @@ import math
 #argParser.add_argument("-n", help="number of bins", type=int, default=50)
 #args = argParser.parse_args()
 
+# input is either ledger tuples "(t, tip)" (latency in slots) or
+# "LATENCY ... seconds=<s>" lines of conf-rule --latency (latency in seconds)
 e = re.compile(r"\(([0-9]+), ([0-9]+)\)")
+e_seconds = re.compile(r"LATENCY .* seconds=([0-9]+)")
 
 confirmed_tip = None
 
@@ -15,19 +18,26 @@ data = []
 min_d = None
 max_d = None
 
+def record(latency):
+    global min_d, max_d
+    data.append(latency)
+    if min_d is None or min_d > latency:
+        min_d = latency
+    if max_d is None or max_d < latency:
+        max_d = latency
+
 for line in sys.stdin:
+    res_seconds = e_seconds.search(line)
+    if res_seconds:
+        record(int(res_seconds.group(1)))
+        continue
     res = e.search(line)
     if res:
         slot = int(res.group(1))
         tip = int(res.group(2))
         if not confirmed_tip is None:
             for i in range(confirmed_tip+1, tip+1):
-                latency = slot - i
-                data.append(latency)
-                if min_d is None or min_d > latency:
-                    min_d = latency
-                if max_d is None or max_d < latency:
-                    max_d = latency
+                record(slot - i)
         confirmed_tip = tip
 
 n_bins = max_d-min_d+1
@@ -0,0 +1,30 @@
+use rocksdb::DB;
+
+use crate::data;
+
+/// Decoded value of a key of the caching database, if present
+pub fn get_decoded<T: serde::de::DeserializeOwned>(db: &DB, key: &str) -> Result<Option<T>, Box<dyn std::error::Error>> {
+    match db.get(key)? {
+        Some(value) => Ok(Some(bincode::deserialize(&value)?)),
+        None => Ok(None),
+    }
+}
+
+/// Root and canonical block of a slot, if the slot is not empty (or not synched)
+pub fn get_canonical_block(db: &DB, slot: usize) -> Result<Option<(data::Root, data::Block)>, Box<dyn std::error::Error>> {
+    match get_decoded::<data::Root>(db, &format!("block_{}", slot))? {
+        Some(root) => {
+            let blk = get_decoded::<data::Block>(db, &format!("block_{}", root))?.ok_or_else(|| format!("Block {} not found", root))?;
+            Ok(Some((root, blk)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Canonical block of a slot, if the slot is not empty, which has to be synched
+pub fn get_block(db: &DB, slot: usize) -> Result<Option<data::Block>, Box<dyn std::error::Error>> {
+    if db.get(format!("slot_{}_synched", slot))?.is_none() {
+        return Err(format!("Slot {} not synched (see `status`)", slot).into());
+    }
+    Ok(get_canonical_block(db, slot)?.map(|(_, blk)| blk))
+}
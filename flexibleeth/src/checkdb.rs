@@ -0,0 +1,240 @@
+use rocksdb::{DB, Options};
+use std::collections::BTreeSet;
+
+use crate::data;
+use crate::utils;
+
+/// Inconsistency found for a slot marked as synched
+struct Problem {
+    slot: usize,
+    repair: Repair,
+    message: String,
+}
+
+/// How a problem is resolved
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Repair {
+    ResyncSlot,
+    ResyncSlotAndEpochState,
+    // the missing data is at an earlier slot (e.g., before the synched range), which syncing
+    // the slot again does not fetch
+    SyncEarlierSlots,
+}
+
+fn get_decoded<T: serde::de::DeserializeOwned>(db: &DB, key: &str) -> Result<Option<Result<T, bincode::Error>>, Box<dyn std::error::Error>> {
+    Ok(db.get(key)?.map(|value| bincode::deserialize::<T>(&value)))
+}
+
+/// Problems of the synched slots in a range, and the number of slots not synched
+fn find_problems(db: &DB, min_slot: usize, max_slot: usize) -> Result<(Vec<Problem>, usize), Box<dyn std::error::Error>> {
+    let is_synched = |slot: usize| -> Result<bool, rocksdb::Error> {
+        Ok(db.get(format!("slot_{}_synched", slot))?.is_some())
+    };
+
+    // latest canonical block before the range, if the slots in between are synched
+    let mut last_block: Option<(usize, data::Root)> = None;
+    for slot in (0..min_slot).rev() {
+        if !is_synched(slot)? {
+            break;
+        }
+        if let Some(Ok(root)) = get_decoded::<data::Root>(db, &format!("block_{}", slot))? {
+            last_block = Some((slot, root));
+            break;
+        }
+    }
+    // epoch of the latest canonical block, or of an unsynched slot (whose epoch's first block is unknown)
+    let mut last_epoch_with_block = match &last_block {
+        Some((slot, _)) => Some(utils::slot_to_epoch(*slot)),
+        None if !utils::is_epoch_boundary_slot(min_slot) => Some(utils::slot_to_epoch(min_slot)),
+        None => None,
+    };
+
+    let mut problems = vec![];
+    let mut num_unsynched = 0;
+    for slot in min_slot..=max_slot {
+        if !is_synched(slot)? {
+            // canonical chain unknown across unsynched slots
+            num_unsynched += 1;
+            last_block = None;
+            last_epoch_with_block = Some(utils::slot_to_epoch(slot));
+            continue;
+        }
+        let mut problem = |repair: Repair, message: String| {
+            problems.push(Problem { slot, repair, message });
+        };
+
+        // canonical block links to the previous canonical block
+        let epoch = utils::slot_to_epoch(slot);
+        let mut blk = None;
+        let mut is_first_block_of_epoch = false;
+        match get_decoded::<data::Root>(db, &format!("block_{}", slot))? {
+            None => {}
+            Some(Err(e)) => problem(Repair::ResyncSlot, format!("canonical block root cannot be decoded: {}", e)),
+            Some(Ok(root)) => {
+                match get_decoded::<data::Block>(db, &format!("block_{}", root))? {
+                    None => problem(Repair::ResyncSlot, format!("block {} not found", root)),
+                    Some(Err(e)) => problem(Repair::ResyncSlot, format!("block {} cannot be decoded: {}", root, e)),
+                    Some(Ok(b)) if b.slot != slot => problem(Repair::ResyncSlot, format!("block {} is for slot {}", root, b.slot)),
+                    Some(Ok(b)) => {
+                        if let Some((last_slot, last_root)) = &last_block {
+                            if b.parent_root != *last_root {
+                                problem(Repair::ResyncSlot, format!(
+                                    "block {} has parent {}, but canonical block at slot {} is {}",
+                                    root, b.parent_root, last_slot, last_root
+                                ));
+                            }
+                        }
+                        blk = Some(b);
+                    }
+                }
+                last_block = Some((slot, root));
+                is_first_block_of_epoch = last_epoch_with_block != Some(epoch);
+                last_epoch_with_block = Some(epoch);
+            }
+        }
+
+        // epoch boundary block is the latest block at or before the boundary
+        if utils::is_epoch_boundary_slot(slot) {
+            match get_decoded::<data::Root>(db, &format!("ebb_{}_root", epoch))? {
+                None => problem(Repair::ResyncSlot, format!("epoch {} boundary block root not found", epoch)),
+                Some(Err(e)) => problem(Repair::ResyncSlot, format!("epoch {} boundary block root cannot be decoded: {}", epoch, e)),
+                Some(Ok(ebb_root)) => {
+                    if let Some((last_slot, last_root)) = &last_block {
+                        if ebb_root != *last_root {
+                            problem(Repair::ResyncSlot, format!(
+                                "epoch {} boundary block is {}, but latest canonical block at slot {} is {}",
+                                epoch, ebb_root, last_slot, last_root
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        // state of the first block of an epoch has committees and finality checkpoints
+        let blk = match blk {
+            Some(blk) if is_first_block_of_epoch => blk,
+            _ => continue,
+        };
+        match get_decoded::<Vec<data::CommitteeAssignment>>(db, &format!("state_{}_committees", blk.state_root))? {
+            None => problem(Repair::ResyncSlotAndEpochState, format!("committees of state {} not found", blk.state_root)),
+            Some(Err(e)) => problem(Repair::ResyncSlotAndEpochState, format!("committees of state {} cannot be decoded: {}", blk.state_root, e)),
+            Some(Ok(_)) => {}
+        }
+        match get_decoded::<(data::Checkpoint, data::Checkpoint, data::Checkpoint)>(
+            db,
+            &format!("state_{}_finality_checkpoints", blk.state_root),
+        )? {
+            None => problem(Repair::ResyncSlotAndEpochState, format!("finality checkpoints of state {} not found", blk.state_root)),
+            Some(Err(e)) => problem(Repair::ResyncSlotAndEpochState, format!("finality checkpoints of state {} cannot be decoded: {}", blk.state_root, e)),
+            Some(Ok((_, _, cp_finalized))) => {
+                let mut cp_finalized_blkroot = cp_finalized.root;
+                if cp_finalized_blkroot == "0x0000000000000000000000000000000000000000000000000000000000000000" {
+                    cp_finalized_blkroot = data::HEADER_GENESIS_ROOT.to_string();
+                }
+                if db.get(format!("block_{}", cp_finalized_blkroot))?.is_none() {
+                    // the finalized block is at an earlier slot that has to be synched
+                    problem(Repair::SyncEarlierSlots, format!("finalized block {} of epoch {} not found", cp_finalized_blkroot, epoch));
+                }
+            }
+        }
+    }
+
+    Ok((problems, num_unsynched))
+}
+
+/// Marks the slots (and epoch states) of the problems as not synched, so that sync fetches them
+/// again, returns the numbers of slots and epochs marked
+fn mark_for_resync(db: &DB, problems: &[Problem]) -> Result<(usize, usize), Box<dyn std::error::Error>> {
+    let slots: BTreeSet<usize> = problems
+        .iter()
+        .filter(|p| p.repair != Repair::SyncEarlierSlots)
+        .map(|p| p.slot)
+        .collect();
+    let epochs: BTreeSet<usize> = problems
+        .iter()
+        .filter(|p| p.repair == Repair::ResyncSlotAndEpochState)
+        .map(|p| utils::slot_to_epoch(p.slot))
+        .collect();
+    for slot in &slots {
+        db.delete(format!("slot_{}_synched", slot))?;
+    }
+    for epoch in &epochs {
+        db.delete(format!("epoch_{}_state_synched", epoch))?;
+    }
+    Ok((slots.len(), epochs.len()))
+}
+
+pub async fn main(db_path: String, min_slot: usize, max_slot: usize, repair: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut db_opts = Options::default();
+    db_opts.increase_parallelism(utils::get_available_cpucores() as i32);
+    db_opts.optimize_for_point_lookup(utils::get_available_ram() as u64 / 4);
+    let db = if repair {
+        DB::open(&db_opts, db_path)?
+    } else {
+        DB::open_for_read_only(&db_opts, db_path, true)?
+    };
+
+    let (problems, num_unsynched) = find_problems(&db, min_slot, max_slot)?;
+    for problem in &problems {
+        println!("PROBLEM slot={} {}", problem.slot, problem.message);
+    }
+    log::info!(
+        "Checked slots {}..={}: {} problems, {} slots not synched",
+        min_slot,
+        max_slot,
+        problems.len(),
+        num_unsynched
+    );
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+    if !repair {
+        return Err(format!("Found {} problems in slots {}..={}", problems.len(), min_slot, max_slot).into());
+    }
+
+    let (num_slots, num_epochs) = mark_for_resync(&db, &problems)?;
+    println!("REPAIRED slots={} epochs={}", num_slots, num_epochs);
+    log::info!("Marked {} slots and {} epoch states as not synched, run sync to fetch them again", num_slots, num_epochs);
+    let num_earlier = problems.iter().filter(|p| p.repair == Repair::SyncEarlierSlots).count();
+    if num_earlier > 0 {
+        log::warn!("{} problems need earlier slots, run sync from before slot {}", num_earlier, min_slot);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn missing_finalized_block_is_not_marked_for_resync() {
+        let db = testutil::TempDb::new("checkdb-finalized");
+        testutil::write_chain(&db, 32 * 6, 4);
+        let (min_slot, max_slot) = (96, 32 * 6 - 1);
+        assert!(find_problems(db.db(), min_slot, max_slot).unwrap().0.is_empty());
+
+        // the finalized block of epoch 3 precedes the range and is gone, and a block in the range is corrupt
+        let finalized_root: data::Root = bincode::deserialize(&db.db().get("ebb_1_root").unwrap().unwrap()).unwrap();
+        db.db().delete(format!("block_{}", finalized_root)).unwrap();
+        let corrupt_slot = (100..).find(|slot| db.db().get(format!("block_{}", slot)).unwrap().is_some()).unwrap();
+        db.db().put(format!("block_{}", corrupt_slot), [0u8; 3]).unwrap();
+
+        let (problems, num_unsynched) = find_problems(db.db(), min_slot, max_slot).unwrap();
+        assert_eq!(num_unsynched, 0);
+        let repairs: Vec<(usize, Repair)> = problems.iter().map(|p| (p.slot, p.repair)).collect();
+        assert!(repairs.contains(&(corrupt_slot, Repair::ResyncSlot)), "{:?}", repairs);
+        assert!(repairs.iter().any(|(slot, repair)| utils::slot_to_epoch(*slot) == 3 && *repair == Repair::SyncEarlierSlots), "{:?}", repairs);
+
+        // the corrupt block and the block linking to it are synched again, the other slots stay synched
+        let resync_slots: BTreeSet<usize> = repairs.iter().filter(|(_, repair)| *repair == Repair::ResyncSlot).map(|(slot, _)| *slot).collect();
+        assert_eq!(mark_for_resync(db.db(), &problems).unwrap(), (resync_slots.len(), 0));
+        for slot in min_slot..=max_slot {
+            let is_synched = db.db().get(format!("slot_{}_synched", slot)).unwrap().is_some();
+            assert_eq!(is_synched, !resync_slots.contains(&slot), "slot {}", slot);
+        }
+    }
+}
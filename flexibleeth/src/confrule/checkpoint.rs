@@ -0,0 +1,71 @@
+use rocksdb::{DB, Options};
+
+use super::replay::ReplayState;
+
+/// Version of the serialized replay state, to be increased whenever its layout changes
+/// (e.g., when rule options are added)
+const CHECKPOINT_VERSION: u32 = 3;
+
+/// Opens (or creates) the store that keeps checkpoints of the confirmation-rule replay.
+/// The caching database is opened read-only by the replay, so checkpoints live separately.
+pub fn open(path: String) -> Result<DB, Box<dyn std::error::Error>> {
+    let mut db_opts = Options::default();
+    db_opts.create_if_missing(true);
+    Ok(DB::open(&db_opts, path)?)
+}
+
+pub fn save(db: &DB, state: &ReplayState) -> Result<(), Box<dyn std::error::Error>> {
+    log::info!("Checkpointing replay state before slot {}", state.next_slot);
+    db.put("checkpoint_version", bincode::serialize(&CHECKPOINT_VERSION)?)?;
+    db.put("checkpoint_latest", bincode::serialize(state)?)?;
+    Ok(())
+}
+
+pub fn load(db: &DB) -> Result<Option<ReplayState>, Box<dyn std::error::Error>> {
+    let serialized_state = match db.get("checkpoint_latest")? {
+        Some(serialized_state) => serialized_state,
+        None => return Ok(None),
+    };
+    // checkpoints written before versioning have no version
+    let version = match db.get("checkpoint_version")? {
+        Some(serialized_version) => bincode::deserialize::<u32>(&serialized_version)?,
+        None => 1,
+    };
+    if version != CHECKPOINT_VERSION {
+        log::error!("Checkpoint has version {}, expected version {}", version, CHECKPOINT_VERSION);
+        return Err("Checkpoint does not match this version, replay without --resume".into());
+    }
+    match bincode::deserialize::<ReplayState>(&serialized_state) {
+        Ok(state) => Ok(Some(state)),
+        Err(e) => {
+            log::error!("Checkpoint cannot be decoded: {}", e);
+            Err("Checkpoint does not match this version, replay without --resume".into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::confrule::replay::Replay;
+    use crate::testutil;
+
+    #[test]
+    fn checkpoint_of_other_version_does_not_match() {
+        let cache = testutil::TempDb::new("checkpoint-cache");
+        let checkpoints = testutil::TempDb::new("checkpoint");
+        let rule_options = testutil::rule_options(&[0.5]);
+        save(checkpoints.db(), Replay::new(cache.db(), rule_options.clone(), 64).get_state()).unwrap();
+        let state = load(checkpoints.db()).unwrap().unwrap();
+        assert_eq!((state.min_slot, state.next_slot, state.rule_options), (64, 64, rule_options));
+
+        checkpoints.db().delete("checkpoint_version").unwrap();
+        let e = load(checkpoints.db()).unwrap_err();
+        assert!(e.to_string().starts_with("Checkpoint does not match"), "{}", e);
+
+        checkpoints.put("checkpoint_version", &CHECKPOINT_VERSION);
+        checkpoints.db().put("checkpoint_latest", [0u8; 3]).unwrap();
+        let e = load(checkpoints.db()).unwrap_err();
+        assert!(e.to_string().starts_with("Checkpoint does not match"), "{}", e);
+    }
+}
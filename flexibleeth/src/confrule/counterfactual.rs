@@ -0,0 +1,83 @@
+use super::replay::LedgerEntry;
+
+/// How the ledger of a counterfactual replay differs from the baseline, for one quorum
+#[derive(Debug, PartialEq)]
+struct LedgerChange {
+    baseline_entries: usize,
+    counterfactual_entries: usize,
+    /// Per covered baseline entry, slots until its tip is covered by the counterfactual (negative if earlier)
+    delays: Vec<i64>,
+}
+
+fn ledger_change(quorum_idx: usize, baseline: &[LedgerEntry], counterfactual: &[LedgerEntry]) -> LedgerChange {
+    let baseline: Vec<&LedgerEntry> = baseline.iter().filter(|e| e.quorum_idx == quorum_idx).collect();
+    let counterfactual: Vec<&LedgerEntry> = counterfactual.iter().filter(|e| e.quorum_idx == quorum_idx).collect();
+
+    // confirmed tips only advance, so the first covering entry is found by bisection
+    let mut delays = vec![];
+    for entry in &baseline {
+        let i = counterfactual.partition_point(|e| e.tip < entry.tip);
+        if let Some(covering) = counterfactual.get(i) {
+            delays.push(covering.slot as i64 - entry.slot as i64);
+        }
+    }
+    LedgerChange { baseline_entries: baseline.len(), counterfactual_entries: counterfactual.len(), delays }
+}
+
+/// Prints, per quorum, how the ledger of a counterfactual replay differs from the baseline:
+/// by how many slots later (or earlier) every confirmed tip of the baseline is covered by the
+/// confirmed tip of the counterfactual, and how many tips it does not cover at all
+pub fn print_ledger_changes(quorums: &[f64], baseline: &[LedgerEntry], counterfactual: &[LedgerEntry]) {
+    for (q_idx, quorum) in quorums.iter().enumerate() {
+        let change = ledger_change(q_idx, baseline, counterfactual);
+        let delays = &change.delays;
+
+        let num_delayed = delays.iter().filter(|d| **d > 0).count();
+        let mean_delay = match delays.is_empty() {
+            true => "NA".to_string(),
+            false => format!("{:.2}", delays.iter().sum::<i64>() as f64 / delays.len() as f64),
+        };
+        let max_delay = delays.iter().max().map_or("NA".to_string(), |d| d.to_string());
+        println!(
+            "LEDGER_CHANGE quorum={} baseline_entries={} counterfactual_entries={} delayed_entries={} unconfirmed_entries={} mean_delay_slots={} max_delay_slots={}",
+            quorum,
+            change.baseline_entries,
+            change.counterfactual_entries,
+            num_delayed,
+            change.baseline_entries - delays.len(),
+            mean_delay,
+            max_delay
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ledger(quorum_idx: usize, slots_and_tips: &[(usize, usize)]) -> Vec<LedgerEntry> {
+        slots_and_tips.iter().map(|&(slot, tip)| LedgerEntry { slot, quorum_idx, tip, vote_slot: slot }).collect()
+    }
+
+    #[test]
+    fn baseline_tips_are_matched_to_first_covering_counterfactual_entry() {
+        let baseline = ledger(0, &[(10, 8), (11, 9), (14, 12), (20, 18)]);
+        // tips 8 and 9 are first covered at slot 12 (by tip 9), tip 12 earlier than in the
+        // baseline (at slot 13), and tip 18 never
+        let counterfactual = ledger(0, &[(12, 9), (13, 12), (16, 15)]);
+        let change = ledger_change(0, &baseline, &counterfactual);
+        assert_eq!(change, LedgerChange { baseline_entries: 4, counterfactual_entries: 3, delays: vec![2, 1, -1] });
+    }
+
+    #[test]
+    fn ledger_changes_are_computed_per_quorum() {
+        let mut baseline = ledger(0, &[(10, 8)]);
+        baseline.extend(ledger(1, &[(12, 8), (13, 10)]));
+        let mut counterfactual = ledger(1, &[(15, 10)]);
+        counterfactual.extend(ledger(0, &[(10, 8)]));
+
+        assert_eq!(ledger_change(0, &baseline, &counterfactual), LedgerChange { baseline_entries: 1, counterfactual_entries: 1, delays: vec![0] });
+        assert_eq!(ledger_change(1, &baseline, &counterfactual), LedgerChange { baseline_entries: 2, counterfactual_entries: 1, delays: vec![3, 2] });
+        assert_eq!(ledger_change(1, &baseline, &[]), LedgerChange { baseline_entries: 2, counterfactual_entries: 0, delays: vec![] });
+    }
+}
@@ -0,0 +1,222 @@
+use rocksdb::{DB, Options};
+use std::collections::{BTreeMap, HashSet};
+
+use super::replay;
+use super::rule::ffg::TargetConfirmationState;
+use crate::cachedb::get_block;
+use crate::utils;
+
+/// Votes for the confirmation target newly counted in a block
+struct BlockVotes {
+    slot: usize,
+    new_votes: usize,   // as counted by the rule
+    votes: usize,
+    new_cast_votes: usize,  // without the length delimiters of the aggregation bits
+    cast_votes: usize,
+    committees: BTreeMap<(usize, usize), usize>,    // slot, index to new cast votes
+}
+
+/// How the votes for the confirmation target of an epoch accumulated
+struct Explanation {
+    target: replay::EpochTarget,
+    num_validators: usize,
+    thresholds: Vec<usize>,
+    blocks: Vec<BlockVotes>,
+    crossed: Vec<Option<(usize, usize)>>,   // per quorum, slot and votes at the crossing
+    votes: usize,
+}
+
+fn explain(db: &DB, epoch: usize, quorums: &[f64]) -> Result<Explanation, Box<dyn std::error::Error>> {
+    // the target is registered at the first block of the epoch
+    let mut first_blk = None;
+    for slot in utils::epoch_to_slot(epoch)..utils::epoch_to_slot(epoch + 1) {
+        if let Some(blk) = get_block(db, slot)? {
+            first_blk = Some(blk);
+            break;
+        }
+    }
+    let first_blk = first_blk.ok_or_else(|| format!("No block in epoch {}", epoch))?;
+    let target = replay::load_epoch_target(db, &first_blk, epoch).map_err(|e| e as Box<dyn std::error::Error>)?;
+    let num_validators: usize = target.committees.iter().map(|committee| committee.validators.len()).sum();
+
+    let mut state = TargetConfirmationState::new(
+        epoch,
+        target.ebb_root.clone(),
+        target.ebb_slot,
+        target.finalized_slot,
+        &target.committees,
+        None,
+        quorums,
+    );
+
+    // the rule counts the length delimiter of the aggregation bits of a committee as a vote,
+    // once at the first contribution of the committee
+    let mut contributed_committees = HashSet::new();
+    let mut cast_votes = 0;
+    let mut blocks = vec![];
+    let mut crossed = vec![None; quorums.len()];
+    // votes of the epoch are included until the end of the following epoch
+    for slot in first_blk.slot..utils::epoch_to_slot(epoch + 2) {
+        let blk = match get_block(db, slot)? {
+            Some(blk) => blk,
+            None => continue,
+        };
+        let (newly_crossed, contributions) = state.process_block_with_contributions(&blk);
+        if !contributions.is_empty() {
+            let committees: BTreeMap<(usize, usize), usize> = contributions
+                .iter()
+                .map(|(committee, votes)| (*committee, votes - contributed_committees.insert(*committee) as usize))
+                .collect();
+            let new_cast_votes: usize = committees.values().sum();
+            cast_votes += new_cast_votes;
+            blocks.push(BlockVotes {
+                slot,
+                new_votes: contributions.values().sum(),
+                votes: state.get_num_votes(),
+                new_cast_votes,
+                cast_votes,
+                committees,
+            });
+        }
+        for q_idx in newly_crossed {
+            crossed[q_idx] = Some((slot, state.get_num_votes()));
+        }
+    }
+
+    Ok(Explanation {
+        target,
+        num_validators,
+        thresholds: state.get_thresholds().to_vec(),
+        blocks,
+        crossed,
+        votes: state.get_num_votes(),
+    })
+}
+
+/// Prints how the votes for the confirmation target of an epoch accumulate block by block
+/// (as counted by the flexible FFG-vote rule), and when each quorum is crossed
+pub async fn main(db_path: String, epoch: usize, quorums: Vec<f64>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut db_opts = Options::default();
+    db_opts.increase_parallelism(utils::get_available_cpucores() as i32);
+    db_opts.optimize_for_point_lookup(utils::get_available_ram() as u64 / 4);
+    let db = DB::open_for_read_only(&db_opts, db_path, true)?;
+
+    let explanation = explain(&db, epoch, &quorums)?;
+    let target = &explanation.target;
+    println!(
+        "TARGET epoch={} ebb_root={} ebb_slot={} finalized_slot={} validators={}",
+        epoch, target.ebb_root, target.ebb_slot, target.finalized_slot, explanation.num_validators
+    );
+    for (quorum, threshold) in quorums.iter().zip(&explanation.thresholds) {
+        println!("THRESHOLD quorum={} votes={}", quorum, threshold);
+    }
+
+    // crossings are printed after the block that crossed them
+    let mut crossings: Vec<(usize, usize)> = (0..quorums.len())
+        .filter_map(|q_idx| explanation.crossed[q_idx].map(|(slot, _)| (slot, q_idx)))
+        .collect();
+    crossings.sort();
+    let mut crossings = crossings.into_iter().peekable();
+    for blk in &explanation.blocks {
+        println!(
+            "BLOCK slot={} new_votes={} votes={} share={:.4} new_cast_votes={} cast_votes={} committees={}",
+            blk.slot,
+            blk.new_votes,
+            blk.votes,
+            blk.votes as f64 / explanation.num_validators as f64,
+            blk.new_cast_votes,
+            blk.cast_votes,
+            blk.committees
+                .iter()
+                .map(|((committee_slot, index), votes)| format!("{}/{}:{}", committee_slot, index, votes))
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+        while let Some((slot, q_idx)) = crossings.next_if(|(slot, _)| *slot == blk.slot) {
+            let (_, votes) = explanation.crossed[q_idx].unwrap();
+            println!(
+                "QUORUM_CROSSED quorum={} slot={} votes={} threshold={} latency_slots={}",
+                quorums[q_idx],
+                slot,
+                votes,
+                explanation.thresholds[q_idx],
+                slot - target.ebb_slot
+            );
+        }
+    }
+    for (q_idx, quorum) in quorums.iter().enumerate() {
+        if explanation.crossed[q_idx].is_none() {
+            println!(
+                "QUORUM_NOT_CROSSED quorum={} votes={} threshold={}",
+                quorum, explanation.votes, explanation.thresholds[q_idx]
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn quorum_crossings_match_replayed_ledger() {
+        let db = testutil::TempDb::new("explain-ledger");
+        testutil::write_chain(&db, 32 * 11, 7);
+        // a quorum beyond the online validators, so that all votes are counted
+        let quorums = [0.5, 0.8, 0.99];
+        let (min_slot, max_slot) = (64, 32 * 10 - 1);
+        let mut replay = replay::Replay::new(db.db(), testutil::rule_options(&quorums), min_slot);
+        let ledger: Vec<(usize, usize, usize)> = (min_slot..=max_slot)
+            .flat_map(|slot| replay.process_slot(slot).unwrap())
+            .map(|entry| (entry.slot, entry.quorum_idx, entry.tip))
+            .collect();
+
+        // the targets of the replayed epochs (those of later epochs cannot cross by the maximum slot)
+        let mut crossings = vec![];
+        for epoch in 2..=9 {
+            let explanation = explain(db.db(), epoch, &quorums).unwrap();
+            // cast votes are the distinct vote positions of the attestations for the target
+            let mut positions: HashSet<(usize, usize, usize)> = HashSet::new();
+            for slot in utils::epoch_to_slot(epoch)..utils::epoch_to_slot(epoch + 2) {
+                for attestation in get_block(db.db(), slot).unwrap().map_or(vec![], |blk| blk.body.attestations) {
+                    if utils::slot_to_epoch(attestation.data.slot) == epoch && attestation.data.target.root == explanation.target.ebb_root {
+                        let aggregation_bits = utils::AggregationBits::new_from_0xhex_str(&attestation.aggregation_bits);
+                        positions.extend(aggregation_bits.get_vote_positions().into_iter().map(|p| (attestation.data.slot, attestation.data.index, p)));
+                    }
+                }
+            }
+            let last = explanation.blocks.last().unwrap();
+            assert_eq!(last.cast_votes, positions.len());
+            assert_eq!(explanation.blocks.iter().map(|blk| blk.new_cast_votes).sum::<usize>(), positions.len());
+            let num_committees = explanation.blocks.iter().flat_map(|blk| blk.committees.keys()).collect::<HashSet<_>>().len();
+            assert_eq!(explanation.votes, positions.len() + num_committees);
+            for (q_idx, crossed) in explanation.crossed.iter().enumerate() {
+                if let Some((slot, _)) = crossed.filter(|(slot, _)| *slot <= max_slot) {
+                    crossings.push((slot, q_idx, explanation.target.finalized_slot));
+                }
+            }
+        }
+        // with --ffg-target finalized, a crossing confirms the finalized checkpoint of the target
+        // if it is past the confirmed tip (one ledger entry per slot and quorum)
+        crossings.sort();
+        let mut tips = vec![0; quorums.len()];
+        let mut expected: Vec<(usize, usize, usize)> = vec![];
+        for (slot, q_idx, finalized_slot) in crossings {
+            if finalized_slot <= tips[q_idx] {
+                continue;
+            }
+            tips[q_idx] = finalized_slot;
+            match expected.last_mut() {
+                Some(entry) if (entry.0, entry.1) == (slot, q_idx) => entry.2 = finalized_slot,
+                _ => expected.push((slot, q_idx, finalized_slot)),
+            }
+        }
+        assert!(expected.iter().any(|(_, q_idx, _)| *q_idx == 1));
+        let mut ledger = ledger;
+        ledger.sort();
+        assert_eq!(ledger, expected);
+    }
+}
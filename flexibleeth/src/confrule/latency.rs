@@ -0,0 +1,54 @@
+use rocksdb::DB;
+
+use super::replay::{LedgerEntry, ReplayError};
+use super::ConfirmationTime;
+use crate::utils;
+
+/// Derives the wall-clock confirmation latency of every canonical block from the ledger,
+/// measured from the start of the block's slot (its proposal) to its confirmation
+pub struct LatencyTracker<'a> {
+    db: &'a DB,
+    genesis_time: u64,
+    confirmation_time: ConfirmationTime,
+    confirmed_tip_slots: Vec<usize>,    // one per quorum, 0 if nothing confirmed yet
+}
+
+impl<'a> LatencyTracker<'a> {
+    pub fn new(db: &'a DB, genesis_time: u64, confirmation_time: ConfirmationTime, confirmed_tip_slots: Vec<usize>) -> Self {
+        Self {
+            db,
+            genesis_time,
+            confirmation_time,
+            confirmed_tip_slots,
+        }
+    }
+
+    /// Returns the slot and latency in seconds of every block newly confirmed by the entry
+    pub fn process_entry(&mut self, entry: &LedgerEntry) -> Result<Vec<(usize, u64)>, ReplayError> {
+        let previous_tip = self.confirmed_tip_slots[entry.quorum_idx];
+        self.confirmed_tip_slots[entry.quorum_idx] = entry.tip;
+        if previous_tip == 0 {
+            // the first confirmation covers everything since genesis, skip it
+            return Ok(vec![]);
+        }
+
+        let confirmed_unixtime = match self.confirmation_time {
+            ConfirmationTime::Inclusion => utils::slot_to_unixtime(entry.slot, self.genesis_time),
+            ConfirmationTime::Attestation => {
+                utils::slot_to_unixtime(entry.vote_slot, self.genesis_time)
+                    + (utils::SECONDS_PER_SLOT / 3) as u64
+            }
+        };
+
+        let mut latencies = vec![];
+        for slot in previous_tip + 1..=entry.tip {
+            if self.db.get(format!("block_{}", slot))?.is_none() {
+                // skip empty slots
+                continue;
+            }
+            let proposed_unixtime = utils::slot_to_unixtime(slot, self.genesis_time);
+            latencies.push((slot, confirmed_unixtime.saturating_sub(proposed_unixtime)));
+        }
+        Ok(latencies)
+    }
+}
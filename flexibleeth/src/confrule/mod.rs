@@ -1,23 +1,174 @@
-use bincode;
 use rocksdb::{DB, Options};
+use std::str::FromStr;
 
+mod checkpoint;
+mod counterfactual;
+pub mod explain;
+mod latency;
+pub mod replay;
 mod rule;
+mod summary;
+pub use rule::{ExclusionMode, FfgTarget, QuorumBase, Rule, RuleOptions};
 use crate::data;
+use crate::metrics;
 use crate::utils;
 
+/// Where and how often to persist the replay state, and whether to resume from it
+pub struct CheckpointOptions {
+    pub db_path: String,
+    pub interval: usize,
+    pub resume: bool,
+}
+
+/// Reference point for the time at which a block counts as confirmed
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum ConfirmationTime {
+    /// Proposal time of the block that includes the votes reaching the quorum
+    Inclusion,
+    /// Attestation deadline in the slot of the latest vote needed to reach the quorum
+    Attestation,
+}
+
+/// How to report wall-clock confirmation latency (LATENCY lines only if a confirmation time
+/// is given), and the genesis time that wall-clock times and the current slot derive from
+pub struct LatencyOptions {
+    pub confirmation_time: Option<ConfirmationTime>,
+    pub genesis_time: u64,
+}
+
+/// Quorums `start:end:step`, e.g. `0.67:0.99:0.01`
+#[derive(Debug, Clone)]
+pub struct QuorumRange(pub Vec<f64>);
+
+impl FromStr for QuorumRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<f64> = s
+            .split(':')
+            .map(|p| p.parse::<f64>().map_err(|e| format!("{}: {}", p, e)))
+            .collect::<Result<_, _>>()?;
+        if parts.len() != 3 {
+            return Err("expected start:end:step".to_string());
+        }
+        let (start, end, step) = (parts[0], parts[1], parts[2]);
+        if !(step > 0.0 && start <= end && start > 0.0 && end <= 1.0) {
+            return Err("expected 0 < start <= end <= 1 and step > 0".to_string());
+        }
+        // round to avoid accumulating floating point error in the printed quorums
+        let n = ((end - start) / step + 1e-9).floor() as usize;
+        Ok(QuorumRange(
+            (0..=n)
+                .map(|i| ((start + i as f64 * step) * 1e9).round() / 1e9)
+                .collect(),
+        ))
+    }
+}
+
+/// Slot window `min_slot:max_slot`
+#[derive(Debug, Clone, Copy)]
+pub struct SlotWindow {
+    pub min_slot: usize,
+    pub max_slot: usize,
+}
+
+impl FromStr for SlotWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((min_slot, max_slot)) => Ok(SlotWindow {
+                min_slot: min_slot.parse().map_err(|e| format!("{}: {}", min_slot, e))?,
+                max_slot: max_slot.parse().map_err(|e| format!("{}: {}", max_slot, e))?,
+            }),
+            None => Err("expected min_slot:max_slot".to_string()),
+        }
+    }
+}
+
+/// Replays every window with every rule options (e.g., one per quorum base, with and without
+/// excluded validators), reporting them side by side
 pub async fn main(
     db_path: String,
-    quorum: Vec<f64>,
-    min_slot: usize,
-    max_slot: usize,
+    rule_options: Vec<RuleOptions>,
+    windows: Vec<SlotWindow>,
+    parallel_threads: Option<usize>,
+    checkpoint_options: Option<CheckpointOptions>,
+    latency_options: LatencyOptions,
+    summary_path: Option<String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    if rule_options.iter().any(|options| options.rule == Rule::FinalityOnly && !options.excluded_validators.is_empty()) {
+        // the replay would not differ from the baseline
+        return Err("Excluded validators do not apply to the finality-only rule".into());
+    }
     let mut db_opts = Options::default();
     // db_opts.create_if_missing(true);
     db_opts.increase_parallelism(utils::get_available_cpucores() as i32);
     db_opts.optimize_level_style_compaction(utils::get_available_ram() / 4);
     db_opts.optimize_for_point_lookup(utils::get_available_ram() as u64 / 4);
     let db = DB::open_for_read_only(&db_opts, db_path, true)?;
+    if rule_options.len() > 1 && checkpoint_options.is_some() {
+        return Err("Checkpoints support a single replay (quorum base, no excluded validators)".into());
+    }
+
+    let mut window_latencies = vec![];
+    for window in &windows {
+        if windows.len() > 1 {
+            println!("WINDOW min_slot={} max_slot={}", window.min_slot, window.max_slot);
+        }
+        let mut ledgers: Vec<(&RuleOptions, Vec<replay::LedgerEntry>)> = vec![];
+        for options in &rule_options {
+            if rule_options.len() > 1 {
+                println!(
+                    "REPLAY quorum_base={} excluded_validators={}",
+                    summary::quorum_base_name(options.quorum_base),
+                    options.excluded_validators.len()
+                );
+            }
+            let mut latencies = summary_path
+                .as_ref()
+                .map(|_| summary::WindowLatencies::new(window.min_slot, window.max_slot, options));
+            let ledger = run_window(
+                &db,
+                options.clone(),
+                *window,
+                parallel_threads,
+                checkpoint_options.as_ref(),
+                &latency_options,
+                latencies.as_mut(),
+            )?;
+            window_latencies.extend(latencies);
+
+            // compare with the replay without excluded validators
+            if !options.excluded_validators.is_empty() {
+                let baseline = ledgers.iter().find(|(baseline_options, _)| {
+                    baseline_options.excluded_validators.is_empty() && baseline_options.quorum_base == options.quorum_base
+                });
+                if let Some((_, baseline_ledger)) = baseline {
+                    counterfactual::print_ledger_changes(&options.quorums, baseline_ledger, &ledger);
+                }
+            }
+            ledgers.push((options, ledger));
+        }
+    }
+
+    if let (Some(summary_path), Some(options)) = (summary_path, rule_options.first()) {
+        summary::write_table(&summary_path, &options.quorums, &window_latencies)?;
+    }
+
+    Ok(())
+}
 
+fn run_window(
+    db: &DB,
+    rule_options: RuleOptions,
+    window: SlotWindow,
+    parallel_threads: Option<usize>,
+    checkpoint_options: Option<&CheckpointOptions>,
+    latency_options: &LatencyOptions,
+    mut latencies: Option<&mut summary::WindowLatencies>,
+) -> Result<Vec<replay::LedgerEntry>, Box<dyn std::error::Error>> {
+    let SlotWindow { min_slot, max_slot } = window;
     // ensure confirmation is up to a reasonable target
     if max_slot < min_slot {
         log::error!(
@@ -26,7 +177,7 @@ pub async fn main(
     }
     let mut max_slot = max_slot;
     let now_unixtime = utils::get_unixtime();
-    let now_slot = utils::unixtime_to_slot(now_unixtime);
+    let now_slot = utils::unixtime_to_slot(now_unixtime, latency_options.genesis_time);
     if max_slot > now_slot - utils::GAP_LATEST_SLOT_NOW_SLOT_CANONICAL_CHAIN_STABILITY {
         let new_max_slot = now_slot - utils::GAP_LATEST_SLOT_NOW_SLOT_CANONICAL_CHAIN_STABILITY;
         log::warn!(
@@ -37,89 +188,194 @@ pub async fn main(
         max_slot = new_max_slot;
     }
 
+    // open checkpoint store and restore replay state if resuming
+    let (checkpoint_db, checkpoint_interval, resume) = match checkpoint_options {
+        Some(options) => (Some(checkpoint::open(options.db_path.clone())?), options.interval, options.resume),
+        None => (None, 0, false),
+    };
+    let mut replay = match (resume, &checkpoint_db) {
+        (true, Some(checkpoint_db)) => {
+            let state = match checkpoint::load(checkpoint_db)? {
+                Some(state) => state,
+                None => {
+                    return Err("No checkpoint found to resume from".into());
+                }
+            };
+            let replay = replay::Replay::from_state(db, state);
+            if replay.get_state().min_slot != min_slot || replay.get_state().rule_options != rule_options {
+                log::error!(
+                    "Checkpoint is for minimum slot {} and {:?}",
+                    replay.get_state().min_slot,
+                    replay.get_state().rule_options
+                );
+                return Err("Checkpoint does not match minimum slot and rule options".into());
+            }
+            if replay.get_state().next_slot > max_slot + 1 {
+                log::error!(
+                    "Checkpoint is at slot {}, past maximum slot {}",
+                    replay.get_state().next_slot,
+                    max_slot
+                );
+                return Err("Checkpoint is past maximum slot".into());
+            }
+            log::info!("Resuming from checkpoint before slot {}", replay.get_state().next_slot);
+            replay
+        }
+        _ => replay::Replay::new(db, rule_options.clone(), min_slot),
+    };
+    let from_slot = replay.get_state().next_slot;
+
     // ensure necessary data has been sync'ed
-    for slot in min_slot..max_slot {
+    let mut unsynched_slots = vec![];
+    for slot in from_slot..max_slot {
         if db.get(format!("slot_{}_synched", slot))?.is_none() {
-            log::error!(
-                "Slot {} not synched",
-                slot
-                );
-            return Err("Sync is not complete".into());
+            unsynched_slots.push(slot);
         }
     }
-
-    // setup confirmation rules
-    let mut conf_rule_states = Vec::new();
-    for (_, q) in quorum.iter().enumerate() {
-        conf_rule_states.push(rule::ConfirmationState::new(*q));
+    if let Some(slot) = unsynched_slots.first() {
+        log::error!(
+            "Slot {} and {} more slots in {}..{} not synched (see `status`, fill gaps with `sync --fill-gaps`)",
+            slot,
+            unsynched_slots.len() - 1,
+            from_slot,
+            max_slot
+            );
+        return Err("Sync is not complete".into());
     }
 
-    let mut last_registered_epoch = utils::slot_to_epoch(min_slot);
-    for slot in min_slot..=max_slot {
-        let blkroot = match &db.get(&format!("block_{}", slot))? {
-            Some(serialized_blkroot) => {
-                bincode::deserialize::<data::Root>(serialized_blkroot)?
+    let quorum = replay.get_quorums().to_vec();
+    // latencies are tracked if reported or summarized, by default from block inclusion
+    let mut latency_tracker = if latency_options.confirmation_time.is_some() || latencies.is_some() {
+        Some(latency::LatencyTracker::new(
+            db,
+            latency_options.genesis_time,
+            latency_options.confirmation_time.unwrap_or(ConfirmationTime::Inclusion),
+            replay.get_confirmed_tip_slots().to_vec(),
+        ))
+    } else {
+        None
+    };
+    let mut ledger = vec![];
+    let mut print_entry = |entry: &replay::LedgerEntry| -> Result<(), Box<dyn std::error::Error>> {
+        print_ledger_entry(&quorum, entry);
+        ledger.push(entry.clone());
+        metrics::CONFRULE_CONFIRMED_TIP_SLOT
+            .with_label_values(&[&quorum[entry.quorum_idx].to_string()])
+            .set(entry.tip as i64);
+        let execution_payload =
+            replay::get_confirmed_execution_payload(db, entry.tip).map_err(|e| e as Box<dyn std::error::Error>)?;
+        print_ledger_execution_entry(&quorum, entry, execution_payload.as_ref());
+        if let Some(execution_payload) = &execution_payload {
+            metrics::CONFRULE_CONFIRMED_EXECUTION_BLOCK_NUMBER
+                .with_label_values(&[&quorum[entry.quorum_idx].to_string()])
+                .set(execution_payload.block_number as i64);
+        }
+        if let Some(latency_tracker) = &mut latency_tracker {
+            for (slot, seconds) in latency_tracker.process_entry(entry).map_err(|e| e as Box<dyn std::error::Error>)? {
+                if latency_options.confirmation_time.is_some() {
+                    println!("LATENCY t={} slot={} quorum={} seconds={}", entry.slot, slot, quorum[entry.quorum_idx], seconds);
+                }
+                if let Some(latencies) = &mut latencies {
+                    latencies.record(entry.quorum_idx, entry.slot - slot, seconds);
+                }
             }
-            None => {
-                continue;
+        }
+        Ok(())
+    };
+
+    if let Some(threads) = parallel_threads {
+        let ledger = replay::replay_parallel(db, &rule_options, min_slot, max_slot, threads)
+            .map_err(|e| e as Box<dyn std::error::Error>)?;
+        for entry in ledger {
+            print_entry(&entry)?;
+        }
+    } else {
+        for slot in from_slot..=max_slot {
+            for entry in replay.process_slot(slot).map_err(|e| e as Box<dyn std::error::Error>)? {
+                print_entry(&entry)?;
             }
-        };
-        let blk = bincode::deserialize::<data::Block>(
-            &db.get(&format!("block_{}", blkroot))?
-                .expect("Block not found when block root is present in db"),
-        )?;
-
-        // if this is the first block of an epoch, register it as a confirmation target
-        let epoch = utils::slot_to_epoch(slot);
-        if epoch > last_registered_epoch {
-            // load committees
-            let committees = bincode::deserialize::<Vec<data::CommitteeAssignment>>(
-                &db.get(&format!("state_{}_committees", blk.state_root))?
-                .expect("Committees not found"),
-                )?;
-
-            // load checkpoint information of what is the confirmation target in question
-            let (_cp_previous_justified, _cp_current_justified, cp_finalized) =
-                bincode::deserialize::<(data::Checkpoint, data::Checkpoint, data::Checkpoint)>(
-                    &db.get(&format!(
-                            "state_{}_finality_checkpoints",
-                            blk.state_root
-                            ))?
-                    .expect("Finality checkpoints not found"),
-                    )?;
-
-            let mut cp_finalized_blkroot = cp_finalized.root;
-            if cp_finalized_blkroot == "0x0000000000000000000000000000000000000000000000000000000000000000" {
-                cp_finalized_blkroot = data::HEADER_GENESIS_ROOT.to_string();
+            metrics::CONFRULE_PROCESSED_SLOT.set(slot as i64);
+            metrics::CONFRULE_TARGET_VOTES.reset();
+            for (epoch, num_votes) in replay.get_target_votes() {
+                metrics::CONFRULE_TARGET_VOTES
+                    .with_label_values(&[&epoch.to_string()])
+                    .set(num_votes as i64);
             }
 
-            // load block information of the confirmation target in question
-            let cp_finalized_blk = bincode::deserialize::<data::Block>(
-                &db.get(&format!("block_{}", cp_finalized_blkroot))?
-                .expect("Block for cp_finalized_blk not found"),
-                )?;
-            log::info!("Registering blkroot {} slot {} as confirmation target for epoch {}", cp_finalized_blkroot, cp_finalized_blk.slot, epoch);
+            // checkpoint periodically at epoch boundaries
+            if let Some(checkpoint_db) = &checkpoint_db {
+                if utils::is_epoch_boundary_slot(slot + 1)
+                    && utils::slot_to_epoch(slot + 1) % checkpoint_interval == 0
+                {
+                    checkpoint::save(checkpoint_db, replay.get_state())?;
+                }
+            }
+        }
+        if let Some(checkpoint_db) = &checkpoint_db {
+            checkpoint::save(checkpoint_db, replay.get_state())?;
+        }
+    }
 
-            let ebb_root = bincode::deserialize::<data::Root>(
-                &db.get(&format!("ebb_{}_root", epoch))?
-                .expect("EBB root for current epoch not found"),
-                )?;
+    Ok(ledger)
+}
 
-            for rule in conf_rule_states.iter_mut() {
-                rule.register_first_block_of_epoch(epoch, ebb_root.clone(), cp_finalized_blk.slot, &committees);
-            }
+fn print_ledger_entry(quorum: &[f64], entry: &replay::LedgerEntry) {
+    println!("LEDGER t={} tip={}, quorum={}", entry.slot, entry.tip, quorum[entry.quorum_idx]);
+}
 
-            last_registered_epoch = epoch;
+fn print_ledger_execution_entry(quorum: &[f64], entry: &replay::LedgerEntry, execution_payload: Option<&data::ExecutionPayload>) {
+    println!(
+        "LEDGER_EXECUTION t={} tip={} quorum={} block_number={} block_hash={}",
+        entry.slot,
+        entry.tip,
+        quorum[entry.quorum_idx],
+        execution_payload.map_or("NA".to_string(), |payload| payload.block_number.to_string()),
+        execution_payload.map_or("NA", |payload| payload.block_hash.as_str())
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quorum_range_from_str() {
+        assert_eq!(QuorumRange::from_str("0.67:0.7:0.01").unwrap().0, vec![0.67, 0.68, 0.69, 0.7]);
+        assert_eq!(QuorumRange::from_str("0.9:0.9:0.05").unwrap().0, vec![0.9]);
+        // end is included only if reached by a whole number of steps
+        assert_eq!(QuorumRange::from_str("0.5:0.7:0.15").unwrap().0, vec![0.5, 0.65]);
+        assert_eq!(QuorumRange::from_str("0.9:0.99:0.01").unwrap().0.len(), 10);
+        for invalid in ["0.67:0.99", "0.67:0.99:0.01:0.1", "a:0.99:0.01", "0.99:0.67:0.01", "0:0.5:0.1", "0.5:1.1:0.1", "0.5:0.9:0"] {
+            assert!(QuorumRange::from_str(invalid).is_err(), "{}", invalid);
         }
-        for rule in conf_rule_states.iter_mut() {
-            match rule.process_block(&blk) {
-                Some(s) => {
-                    println!("LEDGER t={} tip={}, quorum={}", slot, s, rule.get_quorum());
-                }
-                None => {},
-            };
+    }
+
+    #[test]
+    fn slot_window_from_str() {
+        let window = SlotWindow::from_str("6398000:6464000").unwrap();
+        assert_eq!((window.min_slot, window.max_slot), (6398000, 6464000));
+        for invalid in ["6398000", "6398000-6464000", "a:6464000", "6398000:", "-1:5"] {
+            assert!(SlotWindow::from_str(invalid).is_err(), "{}", invalid);
         }
     }
 
-    Ok(())
+    #[tokio::test]
+    async fn finality_only_rule_rejects_excluded_validators() {
+        // rejected before the database is opened
+        let db_path = crate::testutil::TempPath::new("confrule-finality-only-exclusion");
+        let baseline = RuleOptions { rule: Rule::FinalityOnly, ..crate::testutil::rule_options(&[0.5]) };
+        let counterfactual = RuleOptions { excluded_validators: [1, 2].into(), ..baseline.clone() };
+        let e = main(
+            db_path.path_string(),
+            vec![baseline, counterfactual],
+            vec![SlotWindow { min_slot: 40, max_slot: 127 }],
+            None,
+            None,
+            LatencyOptions { confirmation_time: None, genesis_time: 0 },
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(e.to_string(), "Excluded validators do not apply to the finality-only rule");
+    }
 }
@@ -0,0 +1,350 @@
+use rocksdb::DB;
+use serde::{Deserialize, Serialize};
+
+use super::rule;
+use crate::data;
+use crate::utils;
+
+pub type ReplayError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Number of epochs that a replay starting mid-range (a parallel chunk, or a replay again
+/// from a rewritten slot) replays before its first slot, so that all confirmation targets
+/// alive at its first slot are registered and have seen the same votes as in a sequential
+/// run (FFG targets live at most two epochs, three with two-step confirmation, head votes
+/// for a block are included until the end of the second epoch after it), and validator
+/// activity is known for the window before the epoch of each of these targets
+pub fn warmup_epochs(rule_options: &rule::RuleOptions) -> usize {
+    let warmup_epochs = match (rule_options.rule, rule_options.ffg_target) {
+        (rule::Rule::HeadVote, _) | (rule::Rule::FlexibleFfg, rule::FfgTarget::TwoStep) => 3,
+        (rule::Rule::FlexibleFfg, _) | (rule::Rule::FinalityOnly, _) => 2,
+    };
+    match (rule_options.rule, rule_options.quorum_base) {
+        (rule::Rule::FlexibleFfg, rule::QuorumBase::Active) => warmup_epochs + rule_options.active_window_epochs,
+        _ => warmup_epochs,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LedgerEntry {
+    pub slot: usize,
+    pub quorum_idx: usize,
+    pub tip: usize,
+    pub vote_slot: usize,
+}
+
+/// Position and rule state of a replay, persisted in checkpoints to resume a replay
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReplayState {
+    pub min_slot: usize,
+    pub next_slot: usize,
+    pub rule_options: rule::RuleOptions,
+    last_registered_epoch: usize,
+    conf_rule_state: rule::RuleState,
+}
+
+/// Confirmation target of an epoch, registered at the first block of the epoch
+pub struct EpochTarget {
+    pub committees: Vec<data::CommitteeAssignment>,
+    pub ebb_root: data::Root,
+    pub ebb_slot: usize,
+    pub finalized_slot: usize,  // finalized checkpoint in the state of the first block
+}
+
+/// Loads the confirmation target of an epoch, given the first block of the epoch
+pub fn load_epoch_target(db: &DB, blk: &data::Block, epoch: usize) -> Result<EpochTarget, ReplayError> {
+    // load committees
+    let committees = bincode::deserialize::<Vec<data::CommitteeAssignment>>(
+        &db.get(format!("state_{}_committees", blk.state_root))?
+        .expect("Committees not found"),
+        )?;
+
+    // load checkpoint information of what is the confirmation target in question
+    let (_cp_previous_justified, _cp_current_justified, cp_finalized) =
+        bincode::deserialize::<(data::Checkpoint, data::Checkpoint, data::Checkpoint)>(
+            &db.get(format!(
+                    "state_{}_finality_checkpoints",
+                    blk.state_root
+                    ))?
+            .expect("Finality checkpoints not found"),
+            )?;
+
+    let mut cp_finalized_blkroot = cp_finalized.root;
+    if cp_finalized_blkroot == "0x0000000000000000000000000000000000000000000000000000000000000000" {
+        cp_finalized_blkroot = data::HEADER_GENESIS_ROOT.to_string();
+    }
+
+    // load block information of the confirmation target in question
+    let cp_finalized_blk = bincode::deserialize::<data::Block>(
+        &db.get(format!("block_{}", cp_finalized_blkroot))?
+        .expect("Block for cp_finalized_blk not found"),
+        )?;
+    log::info!("Registering blkroot {} slot {} as confirmation target for epoch {}", cp_finalized_blkroot, cp_finalized_blk.slot, epoch);
+
+    let ebb_root = bincode::deserialize::<data::Root>(
+        &db.get(format!("ebb_{}_root", epoch))?
+        .expect("EBB root for current epoch not found"),
+        )?;
+
+    let ebb_blk = bincode::deserialize::<data::Block>(
+        &db.get(format!("block_{}", ebb_root))?
+        .expect("Block for EBB root not found"),
+        )?;
+
+    Ok(EpochTarget {
+        committees,
+        ebb_root,
+        ebb_slot: ebb_blk.slot,
+        finalized_slot: cp_finalized_blk.slot,
+    })
+}
+
+/// Execution block of the latest block at or before a confirmed tip slot, which is the
+/// highest execution block confirmed with the tip (None before the merge, if the blocks
+/// were synched without their execution payloads, or if the slots are not synched)
+pub fn get_confirmed_execution_payload(db: &DB, tip: usize) -> Result<Option<data::ExecutionPayload>, ReplayError> {
+    for slot in (0..=tip).rev() {
+        if let Some(blkroot) = db.get(format!("block_{}", slot))? {
+            let blkroot = bincode::deserialize::<data::Root>(&blkroot)?;
+            return match db.get(format!("block_{}_execution_payload", blkroot))? {
+                Some(execution_payload) => Ok(Some(bincode::deserialize(&execution_payload)?)),
+                None => Ok(None),
+            };
+        }
+        if db.get(format!("slot_{}_synched", slot))?.is_none() {
+            break;
+        }
+    }
+    Ok(None)
+}
+
+/// Replays the confirmation rule slot by slot over the canonical chain in the caching database
+pub struct Replay<'a> {
+    db: &'a DB,
+    state: ReplayState,
+}
+
+impl<'a> Replay<'a> {
+    pub fn new(db: &'a DB, rule_options: rule::RuleOptions, min_slot: usize) -> Self {
+        Self {
+            db,
+            state: ReplayState {
+                min_slot,
+                next_slot: min_slot,
+                rule_options: rule_options.clone(),
+                last_registered_epoch: utils::slot_to_epoch(min_slot),
+                // votes are counted once and shared across all quorums
+                conf_rule_state: rule::RuleState::new(rule_options),
+            },
+        }
+    }
+
+    pub fn from_state(db: &'a DB, state: ReplayState) -> Self {
+        Self { db, state }
+    }
+
+    pub fn get_state(&self) -> &ReplayState {
+        &self.state
+    }
+
+    pub fn get_quorums(&self) -> &[f64] {
+        self.state.conf_rule_state.as_rule().get_quorums()
+    }
+
+    pub fn get_confirmed_tip_slots(&self) -> &[usize] {
+        self.state.conf_rule_state.as_rule().get_confirmed_tip_slots()
+    }
+
+    pub fn get_target_votes(&self) -> Vec<(usize, usize)> {
+        self.state.conf_rule_state.as_rule().get_target_votes()
+    }
+
+    pub fn process_slot(&mut self, slot: usize) -> Result<Vec<LedgerEntry>, ReplayError> {
+        assert!(slot == self.state.next_slot);
+        self.state.next_slot = slot + 1;
+
+        let db = self.db;
+        let blkroot = match &db.get(format!("block_{}", slot))? {
+            Some(serialized_blkroot) => {
+                bincode::deserialize::<data::Root>(serialized_blkroot)?
+            }
+            None => {
+                return Ok(vec![]);
+            }
+        };
+        let blk = bincode::deserialize::<data::Block>(
+            &db.get(format!("block_{}", blkroot))?
+                .expect("Block not found when block root is present in db"),
+        )?;
+
+        // if this is the first block of an epoch, register it as a confirmation target
+        let epoch = utils::slot_to_epoch(slot);
+        if epoch > self.state.last_registered_epoch {
+            let target = load_epoch_target(db, &blk, epoch)?;
+            self.state.conf_rule_state.as_rule_mut().register_first_block_of_epoch(
+                epoch,
+                target.ebb_root,
+                target.ebb_slot,
+                target.finalized_slot,
+                &target.committees,
+            );
+
+            self.state.last_registered_epoch = epoch;
+        }
+
+        Ok(self
+            .state
+            .conf_rule_state
+            .as_rule_mut()
+            .process_block(&blk)
+            .into_iter()
+            .map(|(quorum_idx, tip, vote_slot)| LedgerEntry { slot, quorum_idx, tip, vote_slot })
+            .collect())
+    }
+}
+
+/// Replays the slots `min_slot..=max_slot` in chunks of whole epochs on `threads` threads.
+/// Each chunk warms up on the epochs before it, and the chunk ledgers are merged so that
+/// the result is identical to a sequential replay.
+pub fn replay_parallel(
+    db: &DB,
+    rule_options: &rule::RuleOptions,
+    min_slot: usize,
+    max_slot: usize,
+    threads: usize,
+) -> Result<Vec<LedgerEntry>, ReplayError> {
+    // split range into chunks at epoch boundaries
+    let warmup_epochs = warmup_epochs(rule_options);
+    let first_epoch = utils::slot_to_epoch(min_slot);
+    let last_epoch = utils::slot_to_epoch(max_slot);
+    let num_epochs = last_epoch - first_epoch + 1;
+    let epochs_per_chunk = std::cmp::max(
+        num_epochs.div_ceil(threads),
+        warmup_epochs,
+    );
+    let mut chunks = vec![];
+    let mut chunk_first_epoch = first_epoch;
+    while chunk_first_epoch <= last_epoch {
+        let chunk_min_slot = std::cmp::max(min_slot, utils::epoch_to_slot(chunk_first_epoch));
+        let chunk_max_slot = std::cmp::min(max_slot, utils::epoch_to_slot(chunk_first_epoch + epochs_per_chunk) - 1);
+        let warmup_min_slot = std::cmp::max(
+            min_slot,
+            utils::epoch_to_slot(chunk_first_epoch.saturating_sub(warmup_epochs)),
+        );
+        chunks.push((warmup_min_slot, chunk_min_slot, chunk_max_slot));
+        chunk_first_epoch += epochs_per_chunk;
+    }
+    log::info!("Replaying {} chunks on {} threads", chunks.len(), threads);
+
+    // replay chunks, keeping only ledger entries of the slots owned by the chunk
+    let chunk_ledgers = std::thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .iter()
+            .map(|(warmup_min_slot, chunk_min_slot, chunk_max_slot)| {
+                scope.spawn(move || -> Result<Vec<LedgerEntry>, ReplayError> {
+                    log::debug!("Replaying chunk {}..={} (warmup from {})", chunk_min_slot, chunk_max_slot, warmup_min_slot);
+                    let mut replay = Replay::new(db, rule_options.clone(), *warmup_min_slot);
+                    let mut ledger = vec![];
+                    for slot in *warmup_min_slot..=*chunk_max_slot {
+                        let entries = replay.process_slot(slot)?;
+                        if slot >= *chunk_min_slot {
+                            ledger.extend(entries);
+                        }
+                    }
+                    Ok(ledger)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("Replay thread panicked"))
+            .collect::<Result<Vec<_>, _>>()
+    })?;
+
+    // merge chunk ledgers in slot order; a chunk does not know the tips confirmed
+    // before its warmup, so only entries that advance the overall tip are retained
+    let mut confirmed_tip_slots = vec![0; rule_options.quorums.len()];
+    let mut ledger = vec![];
+    for entry in chunk_ledgers.into_iter().flatten() {
+        if entry.tip > confirmed_tip_slots[entry.quorum_idx] {
+            confirmed_tip_slots[entry.quorum_idx] = entry.tip;
+            ledger.push(entry);
+        }
+    }
+    Ok(ledger)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    fn rule_options(rule: rule::Rule, ffg_target: rule::FfgTarget) -> rule::RuleOptions {
+        rule::RuleOptions { rule, ffg_target, ..testutil::rule_options(&[0.5, 0.8]) }
+    }
+
+    fn replay_sequential(db: &DB, rule_options: &rule::RuleOptions, min_slot: usize, max_slot: usize) -> Vec<LedgerEntry> {
+        let mut replay = Replay::new(db, rule_options.clone(), min_slot);
+        (min_slot..=max_slot).flat_map(|slot| replay.process_slot(slot).unwrap()).collect()
+    }
+
+    fn assert_parallel_matches_sequential(rule_options: &rule::RuleOptions) {
+        let db = testutil::TempDb::new(&format!("replay-parallel-{:?}-{:?}", rule_options.rule, rule_options.ffg_target));
+        testutil::write_chain(&db, 32 * 14, 1);
+        let (min_slot, max_slot) = (70, 32 * 14 - 1);
+        let sequential = replay_sequential(db.db(), rule_options, min_slot, max_slot);
+        assert!(!sequential.is_empty());
+        for threads in [2, 3, 5] {
+            let parallel = replay_parallel(db.db(), rule_options, min_slot, max_slot, threads).unwrap();
+            assert_eq!(parallel, sequential, "{:?} on {} threads", rule_options, threads);
+        }
+    }
+
+    #[test]
+    fn parallel_replay_matches_sequential() {
+        for ffg_target in [rule::FfgTarget::Finalized, rule::FfgTarget::Ebb, rule::FfgTarget::TwoStep] {
+            assert_parallel_matches_sequential(&rule_options(rule::Rule::FlexibleFfg, ffg_target));
+        }
+        for ffg_target in [rule::FfgTarget::Finalized, rule::FfgTarget::TwoStep] {
+            assert_parallel_matches_sequential(&rule::RuleOptions {
+                quorum_base: rule::QuorumBase::Active,
+                ..rule_options(rule::Rule::FlexibleFfg, ffg_target)
+            });
+        }
+        assert_parallel_matches_sequential(&rule_options(rule::Rule::FinalityOnly, rule::FfgTarget::Finalized));
+        assert_parallel_matches_sequential(&rule_options(rule::Rule::HeadVote, rule::FfgTarget::Finalized));
+    }
+
+    #[test]
+    fn validators_voting_for_conflicting_targets_stay_active() {
+        let db = testutil::TempDb::new("replay-exclusion-mode");
+        testutil::write_chain(&db, 32 * 10, 5);
+        let (min_slot, max_slot) = (70, 32 * 10 - 1);
+        let replay = |quorum_base, exclusion_mode| {
+            let rule_options = rule::RuleOptions {
+                quorum_base,
+                // a third of the validators that are online
+                excluded_validators: (0..testutil::NUM_VALIDATORS).filter(|validator| validator % 4 == 1).collect(),
+                exclusion_mode,
+                ..testutil::rule_options(&[0.5, 0.8])
+            };
+            replay_sequential(db.db(), &rule_options, min_slot, max_slot)
+        };
+
+        // relative to all committee members, both modes drop the same votes
+        let offline = replay(rule::QuorumBase::Committee, rule::ExclusionMode::Offline);
+        assert_eq!(replay(rule::QuorumBase::Committee, rule::ExclusionMode::Conflicting), offline);
+
+        // relative to active validators, the excluded validators count only if they are active
+        let offline = replay(rule::QuorumBase::Active, rule::ExclusionMode::Offline);
+        let conflicting = replay(rule::QuorumBase::Active, rule::ExclusionMode::Conflicting);
+        let quorum_entries = |ledger: &[LedgerEntry], quorum_idx| ledger.iter().filter(|e| e.quorum_idx == quorum_idx).count();
+        assert!(quorum_entries(&offline, 1) > 0);
+        assert_eq!(quorum_entries(&conflicting, 1), 0);
+        // with the same votes against more active validators, tips are confirmed no earlier
+        assert!(quorum_entries(&conflicting, 0) > 0);
+        for entry in conflicting.iter() {
+            let covering = offline.iter().find(|e| e.quorum_idx == entry.quorum_idx && e.tip >= entry.tip).unwrap();
+            assert!(covering.slot <= entry.slot, "{:?} after {:?}", covering, entry);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+use crate::data;
+use crate::utils;
+
+/// Validators observed as active, i.e., with a vote included in a block, over the epochs
+/// registered so far
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ValidatorActivity {
+    window_epochs: usize,
+    first_epoch: Option<usize>, // first registered epoch, activity is known from it on
+    committees: HashMap<(usize, usize), Vec<usize>>,    // slot, index to validators of recent epochs
+    last_active_epochs: Vec<Option<usize>>, // validator index to epoch of its latest included vote
+    excluded_validators: BTreeSet<usize>,   // never active
+}
+
+impl ValidatorActivity {
+    pub fn new(window_epochs: usize, excluded_validators: BTreeSet<usize>) -> Self {
+        Self {
+            window_epochs,
+            first_epoch: None,
+            committees: HashMap::new(),
+            last_active_epochs: vec![],
+            excluded_validators,
+        }
+    }
+
+    /// Registers the committees of an epoch, whose members' votes are tracked from then on
+    pub fn register_epoch(&mut self, epoch: usize, committees: &[data::CommitteeAssignment]) {
+        self.first_epoch.get_or_insert(epoch);
+        // attestations are included at most until the end of the following epoch
+        let min_slot = utils::epoch_to_slot(epoch.saturating_sub(1));
+        self.committees.retain(|(slot, _), _| *slot >= min_slot);
+        for committee in committees {
+            self.committees.insert((committee.slot, committee.index), committee.validators.clone());
+        }
+    }
+
+    /// Marks the validators voting in the attestations of the block as active
+    pub fn process_block(&mut self, blk: &data::Block) {
+        for attestation in &blk.body.attestations {
+            let committee = match self.committees.get(&(attestation.data.slot, attestation.data.index)) {
+                Some(committee) => committee,
+                None => continue,   // committees of the epoch not registered
+            };
+            let epoch = utils::slot_to_epoch(attestation.data.slot);
+            let aggregation_bits = utils::AggregationBits::new_from_0xhex_str(&attestation.aggregation_bits);
+            for position in aggregation_bits.get_set_positions() {
+                // positions past the committee are the length delimiter of the bitlist
+                let validator = match committee.get(position) {
+                    Some(validator) => *validator,
+                    None => continue,
+                };
+                if self.excluded_validators.contains(&validator) {
+                    continue;
+                }
+                if self.last_active_epochs.len() <= validator {
+                    self.last_active_epochs.resize(validator + 1, None);
+                }
+                let last_active_epoch = &mut self.last_active_epochs[validator];
+                *last_active_epoch = std::cmp::max(*last_active_epoch, Some(epoch));
+            }
+        }
+    }
+
+    /// Number of members of the committees of an epoch with a vote in the window of epochs
+    /// before it, or `None` if the window reaches before the first registered epoch
+    pub fn count_active(&self, epoch: usize, committees: &[data::CommitteeAssignment]) -> Option<usize> {
+        let first_epoch = self.first_epoch?;
+        if epoch < first_epoch + self.window_epochs {
+            return None;
+        }
+        let min_epoch = epoch - self.window_epochs;
+        Some(
+            committees
+                .iter()
+                .flat_map(|committee| &committee.validators)
+                .filter(|validator| {
+                    self.last_active_epochs
+                        .get(**validator)
+                        .is_some_and(|last_active_epoch| last_active_epoch.is_some_and(|e| e >= min_epoch))
+                })
+                .count(),
+        )
+    }
+}
@@ -0,0 +1,357 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use super::activity::ValidatorActivity;
+use super::{ConfirmationRule, ExclusionMode, FfgTarget, QuorumBase, RuleOptions};
+use crate::data;
+use crate::utils;
+
+/// Flexible FFG-vote rule: once the FFG votes for the epoch boundary block of an epoch reach
+/// the quorum, the rule confirms the finalized checkpoint of the first block of the epoch, or
+/// the epoch boundary block itself (see `FfgTarget`). Quorums are relative to the members of
+/// the committees of the epoch, or to those of them observed as active (see `QuorumBase`).
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FlexibleFfg {
+    quorums: Vec<f64>,
+    ffg_target: FfgTarget,
+    activity: Option<ValidatorActivity>,    // for quorums relative to active validators
+    excluded_validators: BTreeSet<usize>,   // votes not counted
+    last_processed_slot: usize,
+    confirmed_tip_slots: Vec<usize>,    // one per quorum
+    confirmation_targets: Vec<TargetConfirmationState>,
+}
+
+impl FlexibleFfg {
+    pub fn new(options: RuleOptions) -> Self {
+        let n = options.quorums.len();
+        Self {
+            quorums: options.quorums,
+            ffg_target: options.ffg_target,
+            activity: match options.quorum_base {
+                QuorumBase::Committee => None,
+                QuorumBase::Active => Some(ValidatorActivity::new(
+                    options.active_window_epochs,
+                    // validators voting for conflicting targets are still active
+                    match options.exclusion_mode {
+                        ExclusionMode::Offline => options.excluded_validators.clone(),
+                        ExclusionMode::Conflicting => BTreeSet::new(),
+                    },
+                )),
+            },
+            excluded_validators: options.excluded_validators,
+            last_processed_slot: 0,
+            confirmed_tip_slots: vec![0; n],
+            confirmation_targets: vec![],
+        }
+    }
+}
+
+impl ConfirmationRule for FlexibleFfg {
+    fn get_quorums(&self) -> &[f64] {
+        &self.quorums
+    }
+
+    fn get_confirmed_tip_slots(&self) -> &[usize] {
+        &self.confirmed_tip_slots
+    }
+
+    fn get_target_votes(&self) -> Vec<(usize, usize)> {
+        self.confirmation_targets
+            .iter()
+            .map(|t| (t.epoch, t.votes.num_votes))
+            .collect()
+    }
+
+    fn process_block(&mut self, blk: &data::Block) -> Vec<(usize, usize, usize)> {
+        assert!(blk.slot > self.last_processed_slot);
+
+        let mut acted = vec![None; self.quorums.len()];
+
+        if let Some(activity) = &mut self.activity {
+            activity.process_block(blk);
+        }
+        for t in self.confirmation_targets.iter_mut() {
+            for q_idx in t.process_block(blk) {
+                let confirmed_slot = match self.ffg_target {
+                    FfgTarget::Finalized => t.finalized_slot,
+                    FfgTarget::Ebb => t.ebb_slot,
+                    // confirmed by pairs of targets below
+                    FfgTarget::TwoStep => continue,
+                };
+                if self.confirmed_tip_slots[q_idx] < confirmed_slot {
+                    self.confirmed_tip_slots[q_idx] = confirmed_slot;
+                    acted[q_idx] = t.votes.reached[q_idx];
+                }
+            }
+        }
+
+        // the epoch boundary block of a target is confirmed once the target reached the quorum,
+        // and the votes of the following epoch with it as source did as well
+        if self.ffg_target == FfgTarget::TwoStep {
+            for pair in self.confirmation_targets.windows(2) {
+                let (source, target) = (&pair[0], &pair[1]);
+                if target.link_source.as_ref() != Some(&source.vote_target) {
+                    continue;
+                }
+                for (q_idx, tip) in self.confirmed_tip_slots.iter_mut().enumerate() {
+                    if let (Some(source_vote_slot), Some(link_vote_slot)) =
+                        (source.votes.reached[q_idx], target.link_votes.reached[q_idx])
+                    {
+                        if *tip < source.ebb_slot {
+                            *tip = source.ebb_slot;
+                            acted[q_idx] = Some(std::cmp::max(source_vote_slot, link_vote_slot));
+                        }
+                    }
+                }
+            }
+        }
+
+        self.last_processed_slot = blk.slot;
+        acted
+            .iter()
+            .enumerate()
+            .filter_map(|(q_idx, a)| a.map(|vote_slot| (q_idx, self.confirmed_tip_slots[q_idx], vote_slot)))
+            .collect()
+    }
+
+    fn register_first_block_of_epoch(&mut self, epoch: usize, ebb_root: data::Root, ebb_slot: usize, finalized_slot: usize, committees: &[data::CommitteeAssignment]) {
+        // remove confirmation targets that will not receive any more votes, i.e., that are 2
+        // or more epoches old, or 3 or more if votes of the following epoch complete them
+        let retained_epochs = match self.ffg_target {
+            FfgTarget::Finalized | FfgTarget::Ebb => 2,
+            FfgTarget::TwoStep => 3,
+        };
+        self.confirmation_targets.retain(|s| s.epoch + retained_epochs > epoch);
+
+        // votes with the target of the previous epoch as source link the two targets
+        let link_source = match (self.ffg_target, self.confirmation_targets.last()) {
+            (FfgTarget::TwoStep, Some(previous)) if previous.epoch + 1 == epoch => Some(previous.vote_target.clone()),
+            _ => None,
+        };
+        // quorums relative to the validators active in the window before the epoch, or to all
+        // members of its committees until activity is known for the whole window
+        let num_active = self.activity.as_mut().and_then(|activity| {
+            activity.register_epoch(epoch, committees);
+            activity.count_active(epoch, committees)
+        });
+        let mut nc = TargetConfirmationState::new(epoch, ebb_root, ebb_slot, finalized_slot, committees, num_active, &self.quorums);
+        nc.link_source = link_source;
+        nc.excluded_positions = get_excluded_positions(committees, &self.excluded_validators);
+        self.confirmation_targets.push(nc);
+    }
+}
+
+/// Positions of the excluded validators in the committees that contain any
+fn get_excluded_positions(committees: &[data::CommitteeAssignment], excluded_validators: &BTreeSet<usize>) -> HashMap<(usize, usize), Vec<usize>> {
+    let mut excluded_positions = HashMap::new();
+    if excluded_validators.is_empty() {
+        return excluded_positions;
+    }
+    for committee in committees {
+        let positions: Vec<usize> = committee
+            .validators
+            .iter()
+            .enumerate()
+            .filter(|(_, validator)| excluded_validators.contains(validator))
+            .map(|(position, _)| position)
+            .collect();
+        if !positions.is_empty() {
+            excluded_positions.insert((committee.slot, committee.index), positions);
+        }
+    }
+    excluded_positions
+}
+
+/// Votes of a confirmation target, counted once per validator and evaluated against the
+/// thresholds of all quorums
+#[derive(Debug, Serialize, Deserialize)]
+struct VoteCount {
+    vote_aggregators: HashMap<(usize, usize), utils::AggregationBits>,  // slot, index to aggregation bits
+    num_votes: usize,
+    last_vote_slot: usize,  // slot of the latest attestation that contributed new votes
+    reached: Vec<Option<usize>>,    // per quorum, slot of the latest vote needed to reach it
+}
+
+impl VoteCount {
+    fn new(num_quorums: usize) -> Self {
+        Self {
+            vote_aggregators: HashMap::new(),
+            num_votes: 0,
+            last_vote_slot: 0,
+            reached: vec![None; num_quorums],
+        }
+    }
+
+    fn all_reached(&self) -> bool {
+        self.reached.iter().all(|r| r.is_some())
+    }
+
+    /// Counts the votes of the aggregation bits of the attestation (possibly with bits cleared)
+    /// and returns the number of new votes
+    fn add_attestation(&mut self, attestation: &data::Attestation, new_aggregate_aggregator: &utils::AggregationBits) -> usize {
+        let votes_counted_aggregator = self
+            .vote_aggregators
+            .entry((attestation.data.slot, attestation.data.index))
+            .or_insert_with(|| utils::AggregationBits::new_from_0xhex_str_zeroed(&attestation.aggregation_bits));
+
+        let new_votes = votes_counted_aggregator.incorporate_delta(new_aggregate_aggregator);
+        if new_votes.count() > 0 {
+            self.num_votes += new_votes.count();
+            self.last_vote_slot = std::cmp::max(self.last_vote_slot, attestation.data.slot);
+        }
+        new_votes.count()
+    }
+
+    /// Returns the indices of the quorums whose thresholds the votes newly reached
+    fn update_reached(&mut self, thresholds: &[usize]) -> Vec<usize> {
+        let mut newly_reached = vec![];
+        for (q_idx, threshold) in thresholds.iter().enumerate() {
+            if self.reached[q_idx].is_none() && self.num_votes >= *threshold {
+                self.reached[q_idx] = Some(self.last_vote_slot);
+                newly_reached.push(q_idx);
+            }
+        }
+        newly_reached
+    }
+}
+
+/// Vote accounting for a single confirmation target. Votes are aggregated once and
+/// evaluated against the thresholds of all quorums.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TargetConfirmationState {
+    epoch: usize,
+    vote_target: data::Root,
+    ebb_slot: usize,
+    finalized_slot: usize,
+    link_source: Option<data::Root>,    // target of the previous epoch, whose votes link to this one
+    quorums: Vec<usize>,    // vote thresholds, one per quorum
+    committees: HashSet<(usize, usize)>,    // slot, index
+    excluded_positions: HashMap<(usize, usize), Vec<usize>>,    // slot, index to positions of excluded validators
+    votes: VoteCount,
+    link_votes: VoteCount,  // votes with the link source as source
+}
+
+impl TargetConfirmationState {
+    pub fn new(
+        epoch: usize,
+        vote_target: data::Root,
+        ebb_slot: usize,
+        finalized_slot: usize,
+        committees: &[data::CommitteeAssignment],
+        num_active: Option<usize>,
+        quorums: &[f64],
+    ) -> Self {
+        // parse committee info
+        let mut accounting_committees = HashSet::new();
+        let mut accounting_validators = HashSet::new();
+        let mut validators_n: usize = 0;
+        for committee in committees {
+            assert!(committee.slot >= utils::epoch_to_slot(epoch));
+            assert!(committee.slot < utils::epoch_to_slot(epoch+1));
+            let is_new = accounting_committees.insert((committee.slot, committee.index));
+            assert!(is_new);
+
+            for validator in &committee.validators {
+                let is_new = accounting_validators.insert(validator);
+                assert!(is_new);
+                validators_n += 1;
+            }
+        }
+
+        let quorum_base_n = num_active.unwrap_or(validators_n);
+        let validators_q: Vec<usize> = quorums
+            .iter()
+            .map(|q| (quorum_base_n as f64 * q).ceil() as usize)
+            .collect();
+        log::info!("EBB {}: Validator n={} active={:?} q={:?}", vote_target, validators_n, num_active, validators_q);
+
+        Self {
+            epoch,
+            vote_target,
+            ebb_slot,
+            finalized_slot,
+            link_source: None,
+            votes: VoteCount::new(validators_q.len()),
+            link_votes: VoteCount::new(validators_q.len()),
+            quorums: validators_q,
+            committees: accounting_committees,
+            excluded_positions: HashMap::new(),
+        }
+    }
+
+    pub fn get_num_votes(&self) -> usize {
+        self.votes.num_votes
+    }
+
+    /// Vote thresholds, one per quorum
+    pub fn get_thresholds(&self) -> &[usize] {
+        &self.quorums
+    }
+
+    /// Counts the votes in the block and returns the indices of the quorums that this
+    /// target newly reached.
+    pub fn process_block(&mut self, blk: &data::Block) -> Vec<usize> {
+        self.process_block_with_contributions(blk).0
+    }
+
+    /// Like `process_block`, and also returns the number of new votes of every committee
+    /// (slot, index) that contributed to the target in the block
+    pub fn process_block_with_contributions(&mut self, blk: &data::Block) -> (Vec<usize>, BTreeMap<(usize, usize), usize>) {
+        let mut contributions = BTreeMap::new();
+        let count_links = self.link_source.is_some() && !self.link_votes.all_reached();
+        if self.votes.all_reached() && !count_links {
+            return (vec![], contributions);
+        }
+        for attestation in &blk.body.attestations {
+            if attestation.data.slot < utils::epoch_to_slot(self.epoch) {
+                // skip attestations from before the epoch in question
+                continue;
+            }
+            if attestation.data.slot >= utils::epoch_to_slot(self.epoch+1) {
+                // skip attestations from after the epoch in question
+                continue;
+            }
+            if attestation.data.target.root != *self.vote_target {
+                // skip attestations that are not for the target in question
+                continue;
+            }
+
+            assert!(self.committees.contains(&(attestation.data.slot, attestation.data.index)));
+            let mut aggregation_bits = utils::AggregationBits::new_from_0xhex_str(&attestation.aggregation_bits);
+            if let Some(positions) = self.excluded_positions.get(&(attestation.data.slot, attestation.data.index)) {
+                aggregation_bits.clear_positions(positions);
+            }
+            let new_votes = self.votes.add_attestation(attestation, &aggregation_bits);
+            if new_votes > 0 {
+                *contributions.entry((attestation.data.slot, attestation.data.index)).or_insert(0) += new_votes;
+            }
+            if count_links
+                && attestation.data.source.epoch + 1 == self.epoch
+                && self.link_source.as_ref() == Some(&attestation.data.source.root)
+            {
+                self.link_votes.add_attestation(attestation, &aggregation_bits);
+            }
+        }
+
+        if count_links {
+            for q_idx in self.link_votes.update_reached(&self.quorums) {
+                log::info!(
+                    "{}: Link quorum OK! votes={}, quorum={}",
+                    self.vote_target,
+                    self.link_votes.num_votes,
+                    self.quorums[q_idx],
+                );
+            }
+        }
+        let newly_confirmed = self.votes.update_reached(&self.quorums);
+        for q_idx in &newly_confirmed {
+            log::info!(
+                "{}: Quorum OK! votes={}, quorum={}",
+                self.vote_target,
+                self.votes.num_votes,
+                self.quorums[*q_idx],
+            );
+        }
+        (newly_confirmed, contributions)
+    }
+}
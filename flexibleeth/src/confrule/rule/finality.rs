@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+
+use super::ConfirmationRule;
+use crate::data;
+
+/// Finality-only rule: a block is confirmed once it is finalized, i.e., once the state of the
+/// first block of an epoch carries a finalized checkpoint at or after it. Quorums do not
+/// apply, all quorums confirm the same tip.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FinalityOnly {
+    quorums: Vec<f64>,
+    confirmed_tip_slots: Vec<usize>,    // one per quorum
+    finalized_slot: usize,  // finalized checkpoint of the latest registered epoch
+}
+
+impl FinalityOnly {
+    pub fn new(quorums: Vec<f64>) -> Self {
+        let n = quorums.len();
+        Self {
+            quorums,
+            confirmed_tip_slots: vec![0; n],
+            finalized_slot: 0,
+        }
+    }
+}
+
+impl ConfirmationRule for FinalityOnly {
+    fn get_quorums(&self) -> &[f64] {
+        &self.quorums
+    }
+
+    fn get_confirmed_tip_slots(&self) -> &[usize] {
+        &self.confirmed_tip_slots
+    }
+
+    fn process_block(&mut self, blk: &data::Block) -> Vec<(usize, usize, usize)> {
+        let mut confirmations = vec![];
+        for (q_idx, tip) in self.confirmed_tip_slots.iter_mut().enumerate() {
+            if *tip < self.finalized_slot {
+                *tip = self.finalized_slot;
+                // finality is learned from the state of this block
+                confirmations.push((q_idx, *tip, blk.slot));
+            }
+        }
+        confirmations
+    }
+
+    fn register_first_block_of_epoch(&mut self, _epoch: usize, _ebb_root: data::Root, _ebb_slot: usize, finalized_slot: usize, _committees: &[data::CommitteeAssignment]) {
+        self.finalized_slot = std::cmp::max(self.finalized_slot, finalized_slot);
+    }
+}
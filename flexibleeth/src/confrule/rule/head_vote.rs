@@ -0,0 +1,205 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+use super::{ConfirmationRule, RuleOptions};
+use crate::data;
+use crate::utils;
+
+/// Slots after a block during which head votes count towards its support (every validator
+/// attests once per epoch)
+const SUPPORT_WINDOW_SLOTS: usize = utils::SLOTS_PER_EPOCH;
+
+/// Slots after a block during which votes for it may still be included in blocks
+const PENDING_SLOTS: usize = 3 * utils::SLOTS_PER_EPOCH;
+
+/// Head-vote rule: a canonical block is confirmed once the validators whose head votes in the
+/// epoch after the block are for the block or a canonical descendant reach the quorum of the
+/// validators active in the epoch of the block. Confirms individual slots rather than
+/// finalized checkpoints.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HeadVote {
+    quorums: Vec<f64>,
+    last_processed_slot: Option<usize>,
+    confirmed_tip_slots: Vec<usize>,    // one per quorum
+    canonical_slots: HashMap<data::Root, usize>,    // root to slot of recent canonical blocks
+    committees: HashMap<(usize, usize), Vec<usize>>,    // slot, index to validators of recent epochs
+    num_validators: HashMap<usize, usize>,  // epoch to number of validators in committees
+    vote_aggregators: HashMap<(usize, usize), utils::AggregationBits>,  // slot, index to aggregation bits
+    pending_blocks: Vec<PendingBlock>,  // ascending slots
+    excluded_validators: BTreeSet<usize>,   // votes not counted
+}
+
+/// Head votes supporting a canonical block that is not yet confirmed at all quorums
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingBlock {
+    slot: usize,
+    quorums: Vec<usize>,    // vote thresholds, one per quorum
+    supporters: Vec<u64>,   // bitset of validator indices
+    num_votes: usize,
+    last_vote_slot: usize,  // slot of the latest attestation that contributed new votes
+    confirmed: Vec<bool>,   // one per quorum
+}
+
+impl PendingBlock {
+    /// Counts the vote of a validator, unless the validator already supports the block
+    fn add_vote(&mut self, validator: usize, vote_slot: usize) {
+        if self.supporters.len() <= validator / 64 {
+            self.supporters.resize(validator / 64 + 1, 0);
+        }
+        let bit = 1 << (validator % 64);
+        if self.supporters[validator / 64] & bit == 0 {
+            self.supporters[validator / 64] |= bit;
+            self.num_votes += 1;
+            self.last_vote_slot = std::cmp::max(self.last_vote_slot, vote_slot);
+        }
+    }
+}
+
+impl HeadVote {
+    pub fn new(options: RuleOptions) -> Self {
+        let n = options.quorums.len();
+        Self {
+            quorums: options.quorums,
+            last_processed_slot: None,
+            confirmed_tip_slots: vec![0; n],
+            canonical_slots: HashMap::new(),
+            committees: HashMap::new(),
+            num_validators: HashMap::new(),
+            vote_aggregators: HashMap::new(),
+            pending_blocks: vec![],
+            excluded_validators: options.excluded_validators,
+        }
+    }
+
+    /// Counts the new votes of an attestation towards the pending blocks it supports
+    fn process_attestation(&mut self, attestation: &data::Attestation) {
+        let key = (attestation.data.slot, attestation.data.index);
+        let committee = match self.committees.get(&key) {
+            Some(committee) => committee,
+            None => {
+                // committees of the epoch not registered (before the first replayed epoch)
+                return;
+            }
+        };
+        let head_slot = match self.canonical_slots.get(&attestation.data.beacon_block_root) {
+            Some(head_slot) => *head_slot,
+            None => {
+                // head vote for a block off the canonical chain (or too old to matter)
+                return;
+            }
+        };
+
+        let votes_counted_aggregator = self
+            .vote_aggregators
+            .entry(key)
+            .or_insert_with(|| utils::AggregationBits::new_from_0xhex_str_zeroed(&attestation.aggregation_bits));
+        let new_aggregate_aggregator = utils::AggregationBits::new_from_0xhex_str(&attestation.aggregation_bits);
+        let new_votes = votes_counted_aggregator.incorporate_delta(&new_aggregate_aggregator);
+        let validators: Vec<usize> = new_votes
+            .get_set_positions()
+            .into_iter()
+            // positions past the committee are the length delimiter of the bitlist
+            .filter_map(|position| committee.get(position).copied())
+            .filter(|validator| !self.excluded_validators.contains(validator))
+            .collect();
+        if validators.is_empty() {
+            return;
+        }
+
+        // the vote supports the head block and its canonical ancestors within the window,
+        // a range of the pending blocks (ordered by slot)
+        let first = self
+            .pending_blocks
+            .partition_point(|pending_block| pending_block.slot + SUPPORT_WINDOW_SLOTS <= attestation.data.slot);
+        let end = self.pending_blocks.partition_point(|pending_block| pending_block.slot <= head_slot);
+        for pending_block in self.pending_blocks[first..std::cmp::max(first, end)].iter_mut() {
+            for validator in &validators {
+                pending_block.add_vote(*validator, attestation.data.slot);
+            }
+        }
+    }
+}
+
+impl ConfirmationRule for HeadVote {
+    fn get_quorums(&self) -> &[f64] {
+        &self.quorums
+    }
+
+    fn get_confirmed_tip_slots(&self) -> &[usize] {
+        &self.confirmed_tip_slots
+    }
+
+    fn process_block(&mut self, blk: &data::Block) -> Vec<(usize, usize, usize)> {
+        // the root of the previous canonical block is learned from its child
+        if let Some(last_processed_slot) = self.last_processed_slot {
+            assert!(blk.slot > last_processed_slot);
+            self.canonical_slots.insert(blk.parent_root.clone(), last_processed_slot);
+        }
+
+        for attestation in &blk.body.attestations {
+            self.process_attestation(attestation);
+        }
+
+        let mut acted = vec![None; self.quorums.len()];
+        for pending_block in self.pending_blocks.iter_mut() {
+            for (q_idx, quorum) in pending_block.quorums.iter().enumerate() {
+                if !pending_block.confirmed[q_idx] && pending_block.num_votes >= *quorum {
+                    log::debug!(
+                        "Slot {}: Quorum OK! votes={}, quorum={}",
+                        pending_block.slot,
+                        pending_block.num_votes,
+                        quorum
+                    );
+                    pending_block.confirmed[q_idx] = true;
+                    if self.confirmed_tip_slots[q_idx] < pending_block.slot {
+                        self.confirmed_tip_slots[q_idx] = pending_block.slot;
+                        acted[q_idx] = Some(pending_block.last_vote_slot);
+                    }
+                }
+            }
+        }
+
+        // forget blocks that are confirmed or can no longer receive votes
+        self.pending_blocks
+            .retain(|b| !b.confirmed.iter().all(|c| *c) && b.slot + PENDING_SLOTS > blk.slot);
+        self.canonical_slots.retain(|_, slot| *slot + PENDING_SLOTS > blk.slot);
+
+        // the block can be supported by votes in later blocks, if its epoch is registered
+        if let Some(num_validators) = self.num_validators.get(&utils::slot_to_epoch(blk.slot)) {
+            self.pending_blocks.push(PendingBlock {
+                slot: blk.slot,
+                quorums: self
+                    .quorums
+                    .iter()
+                    .map(|q| (*num_validators as f64 * q).ceil() as usize)
+                    .collect(),
+                supporters: vec![],
+                num_votes: 0,
+                last_vote_slot: 0,
+                confirmed: vec![false; self.quorums.len()],
+            });
+        }
+        self.last_processed_slot = Some(blk.slot);
+
+        acted
+            .iter()
+            .enumerate()
+            .filter_map(|(q_idx, a)| a.map(|vote_slot| (q_idx, self.confirmed_tip_slots[q_idx], vote_slot)))
+            .collect()
+    }
+
+    fn register_first_block_of_epoch(&mut self, epoch: usize, _ebb_root: data::Root, _ebb_slot: usize, _finalized_slot: usize, committees: &[data::CommitteeAssignment]) {
+        // attestations are included at most until the end of the following epoch
+        let min_slot = utils::epoch_to_slot(epoch.saturating_sub(2));
+        self.committees.retain(|(slot, _), _| *slot >= min_slot);
+        self.vote_aggregators.retain(|(slot, _), _| *slot >= min_slot);
+        self.num_validators.retain(|e, _| *e + 2 >= epoch);
+
+        let mut num_validators = 0;
+        for committee in committees {
+            num_validators += committee.validators.len();
+            self.committees.insert((committee.slot, committee.index), committee.validators.clone());
+        }
+        self.num_validators.insert(epoch, num_validators);
+    }
+}
@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+use crate::data;
+
+mod activity;
+pub mod ffg;
+mod finality;
+mod head_vote;
+
+/// Confirmation rule replayed block by block over the canonical chain, for several quorums
+/// at once
+pub trait ConfirmationRule {
+    fn get_quorums(&self) -> &[f64];
+
+    /// Confirmed tip slot of every quorum (in quorum order)
+    fn get_confirmed_tip_slots(&self) -> &[usize];
+
+    /// Epoch and number of votes counted of every live confirmation target
+    fn get_target_votes(&self) -> Vec<(usize, usize)> {
+        vec![]
+    }
+
+    /// Registers the first block of an epoch, given the root and slot of the epoch boundary
+    /// block, the slot of the finalized checkpoint in the state of the block, and the
+    /// committees of the epoch. Called before the block is processed.
+    fn register_first_block_of_epoch(&mut self, epoch: usize, ebb_root: data::Root, ebb_slot: usize, finalized_slot: usize, committees: &[data::CommitteeAssignment]);
+
+    /// Processes a block and returns, for every quorum whose confirmed tip advanced,
+    /// the index of the quorum, the new confirmed tip slot, and the slot of the latest
+    /// vote that was needed to reach the quorum (in quorum order).
+    fn process_block(&mut self, blk: &data::Block) -> Vec<(usize, usize, usize)>;
+}
+
+/// Confirmation rules available for replay
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum Rule {
+    /// Flexible FFG-vote rule: confirms the finalized checkpoint once FFG votes for the epoch boundary block reach the quorum
+    FlexibleFfg,
+    /// Confirms blocks once they are finalized (quorums do not apply)
+    FinalityOnly,
+    /// Confirms individual slots once head votes for the block or its descendants in the following epoch reach the quorum
+    HeadVote,
+}
+
+/// What a quorum of FFG votes for an epoch boundary block confirms (`flexible-ffg` rule)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum FfgTarget {
+    /// The finalized checkpoint in the state of the first block of the epoch
+    Finalized,
+    /// The epoch boundary block and its ancestors
+    Ebb,
+    /// The epoch boundary block and its ancestors, once the FFG votes of the following epoch with it as source reach the quorum as well
+    TwoStep,
+}
+
+/// Validators that the quorums of the `flexible-ffg` rule are relative to
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum QuorumBase {
+    /// All members of the committees of the epoch
+    Committee,
+    /// Members of the committees of the epoch with a vote included in the trailing window of epochs before it
+    Active,
+}
+
+/// How the excluded validators of a counterfactual replay behave
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum ExclusionMode {
+    /// Offline: their votes are not counted, and they are never active (`active` quorum base)
+    Offline,
+    /// Voting only for conflicting targets: their votes are not counted, but they are active
+    Conflicting,
+}
+
+/// Confirmation rule to replay and its parameters
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleOptions {
+    pub rule: Rule,
+    pub quorums: Vec<f64>,
+    pub ffg_target: FfgTarget,
+    pub quorum_base: QuorumBase,
+    pub active_window_epochs: usize,
+    pub excluded_validators: BTreeSet<usize>,   // counterfactually, their votes are dropped
+    pub exclusion_mode: ExclusionMode,
+}
+
+/// State of a confirmation rule, serializable for checkpoints
+#[derive(Debug, Serialize, Deserialize)]
+pub enum RuleState {
+    FlexibleFfg(ffg::FlexibleFfg),
+    FinalityOnly(finality::FinalityOnly),
+    HeadVote(head_vote::HeadVote),
+}
+
+impl RuleState {
+    pub fn new(options: RuleOptions) -> Self {
+        match options.rule {
+            Rule::FlexibleFfg => RuleState::FlexibleFfg(ffg::FlexibleFfg::new(options)),
+            Rule::FinalityOnly => RuleState::FinalityOnly(finality::FinalityOnly::new(options.quorums)),
+            Rule::HeadVote => RuleState::HeadVote(head_vote::HeadVote::new(options)),
+        }
+    }
+
+    pub fn as_rule(&self) -> &dyn ConfirmationRule {
+        match self {
+            RuleState::FlexibleFfg(state) => state,
+            RuleState::FinalityOnly(state) => state,
+            RuleState::HeadVote(state) => state,
+        }
+    }
+
+    pub fn as_rule_mut(&mut self) -> &mut dyn ConfirmationRule {
+        match self {
+            RuleState::FlexibleFfg(state) => state,
+            RuleState::FinalityOnly(state) => state,
+            RuleState::HeadVote(state) => state,
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use clap::ValueEnum;
+use std::io::Write;
+
+use super::{QuorumBase, RuleOptions};
+
+/// Name of a quorum base as given on the command line
+pub fn quorum_base_name(quorum_base: QuorumBase) -> String {
+    quorum_base
+        .to_possible_value()
+        .map_or_else(|| format!("{:?}", quorum_base), |value| value.get_name().to_string())
+}
+
+/// Confirmation latencies of the blocks confirmed in one slot window with one quorum base
+/// and set of excluded validators, per quorum
+pub struct WindowLatencies {
+    min_slot: usize,
+    max_slot: usize,
+    quorum_base: QuorumBase,
+    num_excluded_validators: usize,
+    latencies: Vec<(Vec<usize>, Vec<u64>)>,    // one per quorum: latencies in slots, in seconds
+}
+
+impl WindowLatencies {
+    pub fn new(min_slot: usize, max_slot: usize, rule_options: &RuleOptions) -> Self {
+        Self {
+            min_slot,
+            max_slot,
+            quorum_base: rule_options.quorum_base,
+            num_excluded_validators: rule_options.excluded_validators.len(),
+            latencies: vec![(vec![], vec![]); rule_options.quorums.len()],
+        }
+    }
+
+    pub fn record(&mut self, quorum_idx: usize, slots: usize, seconds: u64) {
+        self.latencies[quorum_idx].0.push(slots);
+        self.latencies[quorum_idx].1.push(seconds);
+    }
+}
+
+/// Mean, median, 90th and 99th percentile (nearest rank), and maximum
+fn statistics(values: &[f64]) -> Option<[f64; 5]> {
+    if values.is_empty() {
+        return None;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let percentile = |p: f64| sorted[((p * sorted.len() as f64).ceil() as usize).max(1) - 1];
+    let mean = sorted.iter().sum::<f64>() / sorted.len() as f64;
+    Some([mean, percentile(0.5), percentile(0.9), percentile(0.99), sorted[sorted.len() - 1]])
+}
+
+/// Writes a tab-separated table of latency statistics by window, quorum base, number of
+/// excluded validators and quorum
+pub fn write_table(path: &str, quorum: &[f64], windows: &[WindowLatencies]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut f = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(
+        f,
+        "min_slot\tmax_slot\tquorum_base\texcluded_validators\tquorum\tconfirmed_blocks\tmean_slots\tp50_slots\tp90_slots\tp99_slots\tmax_slots\tmean_seconds\tp50_seconds\tp90_seconds\tp99_seconds\tmax_seconds"
+    )?;
+    for window in windows {
+        for (q_idx, (slots, seconds)) in window.latencies.iter().enumerate() {
+            let slots: Vec<f64> = slots.iter().map(|s| *s as f64).collect();
+            let seconds: Vec<f64> = seconds.iter().map(|s| *s as f64).collect();
+            write!(
+                f,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                window.min_slot,
+                window.max_slot,
+                quorum_base_name(window.quorum_base),
+                window.num_excluded_validators,
+                quorum[q_idx],
+                slots.len()
+            )?;
+            for stats in [statistics(&slots), statistics(&seconds)] {
+                match stats {
+                    Some(stats) => {
+                        for v in stats {
+                            write!(f, "\t{:.2}", v)?;
+                        }
+                    }
+                    None => {
+                        write!(f, "{}", "\tNA".repeat(5))?;
+                    }
+                }
+            }
+            writeln!(f)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statistics_of_latencies() {
+        assert_eq!(statistics(&[]), None);
+        assert_eq!(statistics(&[3.0]), Some([3.0; 5]));
+        let values: Vec<f64> = (1..=100).rev().map(|v| v as f64).collect();
+        assert_eq!(statistics(&values), Some([50.5, 50.0, 90.0, 99.0, 100.0]));
+        // large latencies (in seconds) are not truncated
+        assert_eq!(statistics(&[5_000_000_000.0]), Some([5_000_000_000.0; 5]));
+    }
+}
@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 
 pub type Root = String;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct IdentifiedData<T> {
     pub root: Root,
     pub data: T,
@@ -10,8 +10,10 @@ pub struct IdentifiedData<T> {
 
 pub const HEADER_GENESIS_ROOT: &str =
     "0x4d611d5b93fdab69013a7f0a2f961caca0c853f87cfe9595fe50038163079360";
+pub const GENESIS_STATE_ROOT: &str =
+    "0x7e76880eb67bbdc86250aa578958e9d0675e64e714337855204fb5abaaf82c2b";
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Header {
     pub slot: usize,
     pub proposer_index: usize,
@@ -20,7 +22,7 @@ pub struct Header {
     pub body_root: Root,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Block {
     pub slot: usize,
     pub proposer_index: usize,
@@ -29,18 +31,30 @@ pub struct Block {
     pub body: BlockBody,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct BlockBody {
     pub attestations: Vec<Attestation>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Execution block of a beacon block after the merge, stored apart from the block
+/// (`block_<root>_execution_payload`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExecutionPayload {
+    pub block_number: usize,
+    pub block_hash: Root,
+}
+
+/// Block hash of the default execution payload of the blocks before the merge
+pub const PRE_MERGE_BLOCK_HASH: &str =
+    "0x0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Attestation {
     pub aggregation_bits: String,
     pub data: AttestationData,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AttestationData {
     pub slot: usize,
     pub index: usize,
@@ -49,13 +63,13 @@ pub struct AttestationData {
     pub target: Checkpoint,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Checkpoint {
     pub epoch: usize,
     pub root: Root,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ValidatorAssignment {
     pub index: usize,
     pub balance: usize,
@@ -63,14 +77,16 @@ pub struct ValidatorAssignment {
     pub validator: Validator,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Validator {
     pub pubkey: String,
     pub effective_balance: usize,
     pub slashed: bool,
+    pub activation_epoch: usize,
+    pub exit_epoch: usize,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CommitteeAssignment {
     pub index: usize,
     pub slot: usize,
@@ -0,0 +1,177 @@
+use rocksdb::{DB, Options};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::cachedb::{get_block, get_decoded};
+use crate::data;
+use crate::labels;
+use crate::utils;
+
+/// Entity of validators without an operator label
+const UNLABELED: &str = "unlabeled";
+
+/// Committees of an epoch, from the state of its first block
+fn get_committees(db: &DB, epoch: usize) -> Result<Option<Vec<data::CommitteeAssignment>>, Box<dyn std::error::Error>> {
+    for slot in utils::epoch_to_slot(epoch)..utils::epoch_to_slot(epoch + 1) {
+        if let Some(blk) = get_block(db, slot)? {
+            return get_decoded(db, &format!("state_{}_committees", blk.state_root));
+        }
+    }
+    Ok(None)
+}
+
+/// Votes of the members of an entity for a confirmation target
+#[derive(Default)]
+struct EntityVotes {
+    validators: usize,
+    target_votes: usize,
+    inclusion_delays: Vec<usize>,   // of the first inclusion of every vote for the target
+}
+
+/// Smallest number of entities whose votes for the target reach the threshold, with every
+/// unlabeled validator an entity of its own
+fn min_entities_needed(entity_votes: &BTreeMap<String, EntityVotes>, threshold: usize) -> Option<usize> {
+    let mut votes: Vec<usize> = vec![];
+    for (entity, entity_votes) in entity_votes {
+        if entity == UNLABELED {
+            votes.extend(std::iter::repeat_n(1, entity_votes.target_votes));
+        } else {
+            votes.push(entity_votes.target_votes);
+        }
+    }
+    votes.sort_unstable_by(|a, b| b.cmp(a));
+    let mut total = 0;
+    for (i, v) in votes.iter().enumerate() {
+        if total >= threshold {
+            return Some(i);
+        }
+        total += v;
+    }
+    (total >= threshold).then_some(votes.len())
+}
+
+pub async fn main(
+    db_path: String,
+    min_slot: usize,
+    max_slot: usize,
+    operator_labels_path: String,
+    quorums: Vec<f64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut db_opts = Options::default();
+    db_opts.increase_parallelism(utils::get_available_cpucores() as i32);
+    db_opts.optimize_for_point_lookup(utils::get_available_ram() as u64 / 4);
+    let db = DB::open_for_read_only(&db_opts, db_path, true)?;
+    let labels = labels::load_operator_labels(&operator_labels_path)?;
+    log::info!("Loaded {} operator labels from {}", labels.len(), operator_labels_path);
+
+    for epoch in utils::slot_to_epoch(min_slot)..=utils::slot_to_epoch(max_slot) {
+        let committees = match get_committees(&db, epoch)? {
+            Some(committees) => committees,
+            None => {
+                log::warn!("Committees of epoch {} not found, skipping its confirmation target", epoch);
+                continue;
+            }
+        };
+        let ebb_root = get_decoded::<data::Root>(&db, &format!("ebb_{}_root", epoch))?
+            .ok_or_else(|| format!("EBB root of epoch {} not found", epoch))?;
+
+        // first inclusion of the vote of every committee member for the target, in the blocks
+        // of the epoch and the following one
+        let mut first_inclusion_slots: HashMap<usize, usize> = HashMap::new();
+        let committees_by_key: HashMap<(usize, usize), &Vec<usize>> = committees
+            .iter()
+            .map(|committee| ((committee.slot, committee.index), &committee.validators))
+            .collect();
+        for slot in utils::epoch_to_slot(epoch)..utils::epoch_to_slot(epoch + 2) {
+            let blk = match get_block(&db, slot)? {
+                Some(blk) => blk,
+                None => continue,
+            };
+            for attestation in &blk.body.attestations {
+                if utils::slot_to_epoch(attestation.data.slot) != epoch || attestation.data.target.root != ebb_root {
+                    continue;
+                }
+                let committee = committees_by_key
+                    .get(&(attestation.data.slot, attestation.data.index))
+                    .ok_or_else(|| format!("Committee {} of slot {} not found", attestation.data.index, attestation.data.slot))?;
+                let aggregation_bits = utils::AggregationBits::new_from_0xhex_str(&attestation.aggregation_bits);
+                for position in aggregation_bits.get_vote_positions() {
+                    if let Some(validator) = committee.get(position) {
+                        first_inclusion_slots.entry(*validator).or_insert(blk.slot);
+                    }
+                }
+            }
+        }
+
+        let mut entity_votes: BTreeMap<String, EntityVotes> = BTreeMap::new();
+        let mut num_validators = 0;
+        for committee in &committees {
+            for validator in &committee.validators {
+                let entity = labels.get(validator).map_or(UNLABELED, |label| label.as_str());
+                let votes = entity_votes.entry(entity.to_string()).or_default();
+                votes.validators += 1;
+                if let Some(inclusion_slot) = first_inclusion_slots.get(validator) {
+                    votes.target_votes += 1;
+                    votes.inclusion_delays.push(inclusion_slot - committee.slot);
+                }
+                num_validators += 1;
+            }
+        }
+
+        for (entity, votes) in &entity_votes {
+            let (mean_delay, max_delay) = match votes.inclusion_delays.iter().max() {
+                Some(max_delay) => (
+                    format!("{:.2}", votes.inclusion_delays.iter().sum::<usize>() as f64 / votes.inclusion_delays.len() as f64),
+                    max_delay.to_string(),
+                ),
+                None => ("NA".to_string(), "NA".to_string()),
+            };
+            println!(
+                "ENTITY_VOTES epoch={} entity={} validators={} target_votes={} timely_votes={} mean_inclusion_delay={} max_inclusion_delay={}",
+                epoch,
+                entity,
+                votes.validators,
+                votes.target_votes,
+                votes.inclusion_delays.iter().filter(|d| **d <= 1).count(),
+                mean_delay,
+                max_delay
+            );
+        }
+        for quorum in &quorums {
+            let threshold = (num_validators as f64 * quorum).ceil() as usize;
+            println!(
+                "ENTITIES_NEEDED epoch={} quorum={} threshold={} target_votes={} entities={}",
+                epoch,
+                quorum,
+                threshold,
+                first_inclusion_slots.len(),
+                min_entities_needed(&entity_votes, threshold).map_or("NA".to_string(), |n| n.to_string())
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity_votes(target_votes: &[(&str, usize)]) -> BTreeMap<String, EntityVotes> {
+        target_votes
+            .iter()
+            .map(|(entity, target_votes)| (entity.to_string(), EntityVotes { target_votes: *target_votes, ..Default::default() }))
+            .collect()
+    }
+
+    #[test]
+    fn min_entities_needed_counts_unlabeled_validators_individually() {
+        let votes = entity_votes(&[("a", 10), ("b", 5), ("c", 3), ("idle", 0), (UNLABELED, 4)]);
+        // votes by entity, largest first: 10, 5, 3, 1, 1, 1, 1
+        for (threshold, expected) in [(0, Some(0)), (10, Some(1)), (11, Some(2)), (15, Some(2)), (16, Some(3)), (18, Some(3)), (19, Some(4)), (22, Some(7)), (23, None)] {
+            assert_eq!(min_entities_needed(&votes, threshold), expected, "threshold {}", threshold);
+        }
+        assert_eq!(min_entities_needed(&entity_votes(&[]), 0), Some(0));
+        assert_eq!(min_entities_needed(&entity_votes(&[]), 1), None);
+        assert_eq!(min_entities_needed(&entity_votes(&[(UNLABELED, 3)]), 3), Some(3));
+    }
+}
@@ -0,0 +1,86 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+
+pub const TYPE_VERSION: [u8; 2] = [0x65, 0x32];
+pub const TYPE_COMPRESSED_SIGNED_BEACON_BLOCK: [u8; 2] = [0x01, 0x00];
+pub const TYPE_COMPRESSED_BEACON_STATE: [u8; 2] = [0x02, 0x00];
+
+const HEADER_LENGTH: u64 = 8;
+
+/// Position of an entry in an e2store file
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub entry_type: [u8; 2],
+    offset: u64,
+    length: u64,
+}
+
+/// Reader of e2store files (the container format of ERA files), which loads entry data on demand
+pub struct E2StoreReader {
+    path: String,
+    reader: BufReader<File>,
+    entries: Vec<Entry>,
+}
+
+impl E2StoreReader {
+    /// Opens the file and scans the headers of all entries
+    pub fn open(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let file_length = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut entries = vec![];
+        let mut offset = 0;
+        while offset < file_length {
+            let mut header = [0u8; HEADER_LENGTH as usize];
+            reader.read_exact(&mut header)?;
+            let entry = Entry {
+                entry_type: [header[0], header[1]],
+                offset: offset + HEADER_LENGTH,
+                length: u32::from_le_bytes(header[2..6].try_into().unwrap()) as u64,
+            };
+            if header[6..8] != [0, 0] || entry.offset + entry.length > file_length {
+                return Err(format!("Invalid e2store entry at offset {} in {}", offset, path).into());
+            }
+            offset = entry.offset + entry.length;
+            reader.seek(SeekFrom::Start(offset))?;
+            entries.push(entry);
+        }
+        if entries.first().map(|e| e.entry_type) != Some(TYPE_VERSION) {
+            return Err(format!("{} is not an e2store file", path).into());
+        }
+
+        Ok(Self {
+            path: path.to_string(),
+            reader,
+            entries,
+        })
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn entries_of_type(&self, entry_type: [u8; 2]) -> Vec<Entry> {
+        self.entries
+            .iter()
+            .filter(|e| e.entry_type == entry_type)
+            .cloned()
+            .collect()
+    }
+
+    /// Reads the data of an entry, decompressing it (snappy framing format) if it is compressed
+    pub fn read(&mut self, entry: &Entry) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        self.reader.seek(SeekFrom::Start(entry.offset))?;
+        let mut data = vec![0u8; entry.length as usize];
+        self.reader.read_exact(&mut data)?;
+        match entry.entry_type {
+            TYPE_COMPRESSED_SIGNED_BEACON_BLOCK | TYPE_COMPRESSED_BEACON_STATE => {
+                let mut decompressed = vec![];
+                snap::read::FrameDecoder::new(&data[..]).read_to_end(&mut decompressed)?;
+                Ok(decompressed)
+            }
+            _ => Ok(data),
+        }
+    }
+}
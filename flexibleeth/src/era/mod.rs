@@ -0,0 +1,479 @@
+use rocksdb::{DB, Options};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+mod e2store;
+mod ssz;
+use crate::data;
+use crate::shuffling;
+use crate::utils;
+
+pub async fn main(db_path: String, era_files: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut db_opts = Options::default();
+    db_opts.create_if_missing(true);
+    db_opts.increase_parallelism(utils::get_available_cpucores() as i32);
+    db_opts.optimize_level_style_compaction(utils::get_available_ram() / 4);
+    db_opts.optimize_for_point_lookup(utils::get_available_ram() as u64 / 4);
+    let db = DB::open(&db_opts, db_path)?;
+
+    // order ERA files by the slot of their final state
+    let mut eras = vec![];
+    for path in era_files {
+        let mut reader = e2store::E2StoreReader::open(&path)?;
+        let state = read_state(&mut reader)?;
+        log::debug!("ERA file {} ends at slot {}", path, state.slot);
+        eras.push((state.slot, path));
+    }
+    eras.sort();
+
+    // the blocks of an ERA file are imported based on the final states of the preceding
+    // and of the ERA file itself
+    let mut start: Option<(ssz::State, String)> = None;
+    for (_, path) in eras {
+        let mut reader = e2store::E2StoreReader::open(&path)?;
+        let end_state = read_state(&mut reader)?;
+        match &start {
+            Some((start_state, start_path)) if start_state.slot + ssz::SLOTS_PER_HISTORICAL_ROOT == end_state.slot => {
+                let missing_roots = import_era(&db, &mut reader, start_state, &end_state)?;
+                if !missing_roots.is_empty() {
+                    // the blocks of the ERA file providing the starting state are not imported,
+                    // except for those the era refers to
+                    import_referenced_blocks(&db, &mut e2store::E2StoreReader::open(start_path)?, start_state, &missing_roots)?;
+                }
+            }
+            _ => {
+                log::warn!(
+                    "ERA file of the era preceding {} not given, using it only as starting state",
+                    path
+                );
+            }
+        }
+        start = Some((end_state, path));
+    }
+
+    Ok(())
+}
+
+fn read_state(reader: &mut e2store::E2StoreReader) -> Result<ssz::State, Box<dyn std::error::Error>> {
+    let entries = reader.entries_of_type(e2store::TYPE_COMPRESSED_BEACON_STATE);
+    if entries.len() != 1 {
+        return Err(format!("ERA file {} does not contain exactly one state", reader.get_path()).into());
+    }
+    ssz::decode_state(&reader.read(&entries[0])?)
+}
+
+/// Imports the blocks of an era and the data of its epochs. Returns the roots of the blocks
+/// before the era that its epochs refer to (as finalized checkpoint or epoch boundary block)
+/// and that are not in the database.
+fn import_era(
+    db: &DB,
+    reader: &mut e2store::E2StoreReader,
+    start_state: &ssz::State,
+    end_state: &ssz::State,
+) -> Result<HashSet<data::Root>, Box<dyn std::error::Error>> {
+    if start_state.fork_version[0] >= ssz::FORK_VERSION_ELECTRA {
+        return Err(format!("ERA file {}: blocks from Electra onwards are not supported", reader.get_path()).into());
+    }
+    let min_slot = start_state.slot;
+    let max_slot = end_state.slot;
+    log::info!("Importing slots {}..{} from {}", min_slot, max_slot, reader.get_path());
+
+    // blocks, with roots from the final state
+    let mut first_block_state_roots = BTreeMap::new(); // epoch to state root of first block
+    let mut justified = HashMap::new(); // target epoch to source of attestations
+    let mut parent_root = if min_slot == 0 {
+        // the genesis block is not in the ERA file of the first era
+        let genesis_blk = data::Block {
+            slot: 0,
+            proposer_index: 0,
+            parent_root: "0x0000000000000000000000000000000000000000000000000000000000000000".to_string(),
+            state_root: data::GENESIS_STATE_ROOT.to_string(),
+            body: data::BlockBody { attestations: vec![] },
+        };
+        db.put(format!("block_{}", 0), bincode::serialize(data::HEADER_GENESIS_ROOT)?)?;
+        db.put(format!("block_{}", data::HEADER_GENESIS_ROOT), bincode::serialize(&genesis_blk)?)?;
+        first_block_state_roots.insert(0, genesis_blk.state_root);
+        data::HEADER_GENESIS_ROOT.to_string()
+    } else {
+        start_state.get_block_root_at_slot(min_slot - 1)
+    };
+    for entry in reader.entries_of_type(e2store::TYPE_COMPRESSED_SIGNED_BEACON_BLOCK) {
+        let blk_bytes = reader.read(&entry)?;
+        let blk = ssz::decode_signed_block(&blk_bytes)?;
+        if blk.slot < min_slot || blk.slot >= max_slot {
+            return Err(format!("Block at slot {} outside of era {}..{}", blk.slot, min_slot, max_slot).into());
+        }
+        let blk_root = end_state.get_block_root_at_slot(blk.slot);
+        if blk.parent_root != parent_root || blk_root == parent_root {
+            return Err(format!(
+                "Block at slot {} does not link to parent {} in block roots of final state",
+                blk.slot, parent_root
+            )
+            .into());
+        }
+
+        for attestation in &blk.body.attestations {
+            let source = justified
+                .entry(attestation.data.target.epoch)
+                .or_insert_with(|| attestation.data.source.clone());
+            if *source != attestation.data.source {
+                log::warn!(
+                    "Attestations for target epoch {} with different sources {:?} and {:?}",
+                    attestation.data.target.epoch,
+                    source,
+                    attestation.data.source
+                );
+            }
+        }
+        first_block_state_roots
+            .entry(utils::slot_to_epoch(blk.slot))
+            .or_insert_with(|| blk.state_root.clone());
+
+        log::debug!("Block at slot {}: {}", blk.slot, blk_root);
+        db.put(format!("block_{}", blk.slot), bincode::serialize(&blk_root)?)?;
+        db.put(format!("block_{}", blk_root), bincode::serialize(&blk)?)?;
+        if start_state.fork_version[0] >= ssz::FORK_VERSION_BELLATRIX {
+            if let Some(execution_payload) = ssz::decode_signed_block_execution_payload(&blk_bytes)? {
+                db.put(format!("block_{}_execution_payload", blk_root), bincode::serialize(&execution_payload)?)?;
+            }
+        }
+        parent_root = blk_root;
+    }
+
+    // epoch boundary blocks (latest block at or before the boundary)
+    let mut referenced_roots = HashSet::new();
+    for epoch in utils::slot_to_epoch(min_slot)..utils::slot_to_epoch(max_slot) {
+        let ebb_root = end_state.get_block_root_at_slot(utils::epoch_to_slot(epoch));
+        log::debug!("Epoch {} boundary block: {}", epoch, ebb_root);
+        db.put(format!("ebb_{}_root", epoch), bincode::serialize(&ebb_root)?)?;
+        referenced_roots.insert(ebb_root);
+    }
+
+    // states at the first blocks of epochs: finality checkpoints and committees
+    let checkpoints = track_finality_checkpoints(start_state, end_state, &justified);
+    let epochs: Vec<usize> = first_block_state_roots.keys().copied().collect();
+    let committees = compute_committees(end_state, &epochs);
+    for ((epoch, state_root), committees) in first_block_state_roots.iter().zip(committees) {
+        let epoch_checkpoints = &checkpoints[epoch - utils::slot_to_epoch(min_slot)];
+        log::debug!("Epoch {} finality checkpoints: {:?}", epoch, epoch_checkpoints);
+        db.put(
+            format!("state_{}_finality_checkpoints", state_root),
+            bincode::serialize(epoch_checkpoints)?,
+        )?;
+        db.put(format!("state_{}_committees", state_root), bincode::serialize(&committees)?)?;
+        db.put(format!("epoch_{}_state_synched", epoch), bincode::serialize(&true)?)?;
+        let (_, _, cp_finalized) = epoch_checkpoints;
+        if cp_finalized.root != "0x0000000000000000000000000000000000000000000000000000000000000000" {
+            referenced_roots.insert(cp_finalized.root.clone());
+        }
+    }
+
+    for slot in min_slot..max_slot {
+        db.put(format!("slot_{}_synched", slot), bincode::serialize(&true)?)?;
+    }
+
+    let mut missing_roots = HashSet::new();
+    for root in referenced_roots {
+        if db.get(format!("block_{}", root))?.is_none() {
+            missing_roots.insert(root);
+        }
+    }
+    Ok(missing_roots)
+}
+
+/// Imports the blocks with the given roots from an ERA file (without marking their slots as
+/// synched), as the finalized checkpoints and epoch boundary blocks of the following era
+fn import_referenced_blocks(
+    db: &DB,
+    reader: &mut e2store::E2StoreReader,
+    end_state: &ssz::State,
+    roots: &HashSet<data::Root>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut num_imported = 0;
+    for entry in reader.entries_of_type(e2store::TYPE_COMPRESSED_SIGNED_BEACON_BLOCK) {
+        let blk = ssz::decode_signed_block(&reader.read(&entry)?)?;
+        let blk_root = end_state.get_block_root_at_slot(blk.slot);
+        if roots.contains(&blk_root) {
+            log::debug!("Block at slot {} before the era: {}", blk.slot, blk_root);
+            db.put(format!("block_{}", blk_root), bincode::serialize(&blk)?)?;
+            num_imported += 1;
+        }
+    }
+    if num_imported < roots.len() {
+        log::warn!(
+            "{} blocks referenced as finalized checkpoints or epoch boundary blocks precede {}, conf-rule needs an earlier starting ERA file",
+            roots.len() - num_imported,
+            reader.get_path()
+        );
+    }
+    Ok(())
+}
+
+/// Finality checkpoints (previous justified, current justified, finalized) of the states in
+/// every epoch of the era, which ERA files do not contain. Starting from the checkpoints of
+/// the state at the start of the era, the justified checkpoint of each epoch is the source of
+/// the attestations targeting it, and finalization follows `weigh_justification_and_finalization`.
+/// If two epochs are justified in the same epoch transition, only the later one is seen, so
+/// the result is verified against the state at the end of the era.
+fn track_finality_checkpoints(
+    start_state: &ssz::State,
+    end_state: &ssz::State,
+    justified: &HashMap<usize, data::Checkpoint>,
+) -> Vec<(data::Checkpoint, data::Checkpoint, data::Checkpoint)> {
+    let first_epoch = utils::slot_to_epoch(start_state.slot);
+    let last_epoch = utils::slot_to_epoch(end_state.slot);
+
+    let mut justification_bits = start_state.justification_bits & 0x0f; // bit i: epoch current-i justified
+    let mut previous_justified = start_state.previous_justified.clone();
+    let mut current_justified = start_state.current_justified.clone();
+    let mut finalized = start_state.finalized.clone();
+    let mut checkpoints = vec![(previous_justified.clone(), current_justified.clone(), finalized.clone())];
+
+    for epoch in first_epoch + 1..=last_epoch {
+        // epoch transition at the end of the preceding epoch
+        let current_epoch = epoch - 1;
+        let old_previous_justified = previous_justified.clone();
+        let old_current_justified = current_justified.clone();
+        previous_justified = current_justified.clone();
+        justification_bits = (justification_bits << 1) & 0x0f;
+        let new_justified = if epoch == last_epoch {
+            Some(&end_state.current_justified)
+        } else {
+            justified.get(&epoch)
+        };
+        if let Some(new_justified) = new_justified {
+            if new_justified.epoch + 1 == current_epoch {
+                justification_bits |= 0b0010;
+            } else if new_justified.epoch == current_epoch {
+                justification_bits |= 0b0001;
+                if old_current_justified.epoch + 1 < current_epoch {
+                    log::warn!(
+                        "Epoch {} justified while epoch {} was not, whether both were justified in the same transition is unknown and finality checkpoints may be inaccurate",
+                        current_epoch,
+                        current_epoch - 1
+                    );
+                }
+            }
+            current_justified = new_justified.clone();
+        }
+
+        if justification_bits & 0b1110 == 0b1110 && old_previous_justified.epoch + 3 == current_epoch {
+            finalized = old_previous_justified.clone();
+        }
+        if justification_bits & 0b0110 == 0b0110 && old_previous_justified.epoch + 2 == current_epoch {
+            finalized = old_previous_justified.clone();
+        }
+        if justification_bits & 0b0111 == 0b0111 && old_current_justified.epoch + 2 == current_epoch {
+            finalized = old_current_justified.clone();
+        }
+        if justification_bits & 0b0011 == 0b0011 && old_current_justified.epoch + 1 == current_epoch {
+            finalized = old_current_justified.clone();
+        }
+
+        if epoch < last_epoch {
+            checkpoints.push((previous_justified.clone(), current_justified.clone(), finalized.clone()));
+        }
+    }
+
+    if previous_justified != end_state.previous_justified
+        || finalized != end_state.finalized
+        || justification_bits != end_state.justification_bits & 0x0f
+    {
+        log::warn!(
+            "Finality checkpoints tracked up to slot {} differ from final state of era, checkpoints of the era may be inaccurate",
+            end_state.slot
+        );
+    }
+    checkpoints
+}
+
+/// Committees of the given epochs, from the validator registry and RANDAO mixes of the final
+/// state, computed on all CPU cores
+fn compute_committees(end_state: &ssz::State, epochs: &[usize]) -> Vec<Vec<data::CommitteeAssignment>> {
+    if epochs.is_empty() {
+        return vec![];
+    }
+    let epochs_per_thread = epochs.len().div_ceil(utils::get_available_cpucores().max(1));
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = epochs
+            .chunks(epochs_per_thread)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|epoch| {
+                            shuffling::get_beacon_committees(
+                                *epoch,
+                                &end_state.get_active_validator_indices(*epoch),
+                                &end_state.get_attester_seed(*epoch),
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().expect("Committee computation thread panicked"))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::confrule::replay;
+    use crate::testutil;
+    use std::io::Write;
+
+    const NUM_VALIDATORS: usize = 2048;
+
+    fn root(slot: usize) -> data::Root {
+        ssz::bytes_to_0xhex(&shuffling::get_attester_seed(slot, &[0xbb; 32]))
+    }
+
+    fn checkpoint(epoch: usize, root: &str) -> data::Checkpoint {
+        data::Checkpoint { epoch, root: root.to_string() }
+    }
+
+    /// State at `slot` whose block roots are those of the latest of the given blocks
+    fn state(slot: usize, blocks: &[(usize, data::Root)], checkpoints: [data::Checkpoint; 3]) -> ssz::State {
+        let mut block_roots = vec![[0u8; 32]; ssz::SLOTS_PER_HISTORICAL_ROOT];
+        for s in slot.saturating_sub(ssz::SLOTS_PER_HISTORICAL_ROOT)..slot {
+            if let Some((_, blkroot)) = blocks.iter().rev().find(|(blk_slot, _)| *blk_slot <= s) {
+                block_roots[s % ssz::SLOTS_PER_HISTORICAL_ROOT] = shuffling::bytes32_from_0xhex(blkroot).unwrap();
+            }
+        }
+        let [previous_justified, current_justified, finalized] = checkpoints;
+        ssz::State {
+            slot,
+            fork_version: [0, 0, 0, 0],
+            block_roots,
+            randao_mixes: (0..shuffling::EPOCHS_PER_HISTORICAL_VECTOR).map(|i| [(i % 256) as u8; 32]).collect(),
+            validator_epochs: vec![(0, usize::MAX); NUM_VALIDATORS],
+            justification_bits: 0,
+            previous_justified,
+            current_justified,
+            finalized,
+        }
+    }
+
+    /// Blocks at the given slots, each linking to the preceding one
+    fn blocks(slots: &[usize], parent_root: &str) -> Vec<data::Block> {
+        let mut parent_root = parent_root.to_string();
+        slots
+            .iter()
+            .map(|slot| data::Block {
+                slot: *slot,
+                proposer_index: slot % 100,
+                parent_root: std::mem::replace(&mut parent_root, root(*slot)),
+                state_root: ssz::bytes_to_0xhex(&[(slot % 256) as u8; 32]),
+                body: data::BlockBody { attestations: vec![] },
+            })
+            .collect()
+    }
+
+    fn compressed(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = snap::write::FrameEncoder::new(vec![]);
+        encoder.write_all(bytes).unwrap();
+        encoder.into_inner().unwrap()
+    }
+
+    fn write_era_file(path: &std::path::Path, blocks: &[data::Block], state: &ssz::State) {
+        let mut entries = vec![(e2store::TYPE_VERSION, vec![])];
+        for blk in blocks {
+            entries.push((
+                e2store::TYPE_COMPRESSED_SIGNED_BEACON_BLOCK,
+                compressed(&ssz::encode::signed_block(blk, &ssz::encode::Fork::Phase0)),
+            ));
+        }
+        entries.push((e2store::TYPE_COMPRESSED_BEACON_STATE, compressed(&ssz::encode::state(state))));
+        let mut file = std::fs::File::create(path).unwrap();
+        for (entry_type, data) in entries {
+            file.write_all(&entry_type).unwrap();
+            file.write_all(&(data.len() as u32).to_le_bytes()).unwrap();
+            file.write_all(&[0, 0]).unwrap();
+            file.write_all(&data).unwrap();
+        }
+    }
+
+    fn get<T: serde::de::DeserializeOwned>(db: &DB, key: &str) -> Option<T> {
+        db.get(key).unwrap().map(|value| bincode::deserialize(&value).unwrap())
+    }
+
+    /// Replays the confirmation rule on the imported slots, which needs the finalized
+    /// checkpoint and epoch boundary blocks of every epoch
+    fn replay(db: &DB, min_slot: usize, max_slot: usize) {
+        let rule_options = testutil::rule_options(&[0.5]);
+        let mut replay = replay::Replay::new(db, rule_options, min_slot);
+        for slot in min_slot..=max_slot {
+            replay.process_slot(slot).unwrap();
+        }
+    }
+
+    /// Imports the ERA files (written in this order) to a new database
+    async fn import(name: &str, eras: &[(&[data::Block], &ssz::State)]) -> testutil::TempDb {
+        let dir = testutil::TempPath::new(&format!("{}-files", name));
+        std::fs::create_dir_all(dir.path()).unwrap();
+        let mut paths = vec![];
+        for (i, (blks, state)) in eras.iter().enumerate() {
+            let path = dir.path().join(format!("mainnet-{:05}.era", i));
+            write_era_file(&path, blks, state);
+            paths.push(path.to_str().unwrap().to_string());
+        }
+        let db_path = testutil::TempPath::new(name);
+        main(db_path.path_string(), paths).await.unwrap();
+        testutil::TempDb::open(db_path)
+    }
+
+    #[tokio::test]
+    async fn import_first_era_from_genesis_state() {
+        let zero_root = "0x0000000000000000000000000000000000000000000000000000000000000000";
+        let zero_checkpoints = [checkpoint(0, zero_root), checkpoint(0, zero_root), checkpoint(0, zero_root)];
+        let blks = blocks(&[1, 2, 40, 8000], data::HEADER_GENESIS_ROOT);
+        let mut roots = vec![(0, data::HEADER_GENESIS_ROOT.to_string())];
+        roots.extend(blks.iter().map(|blk| (blk.slot, root(blk.slot))));
+        let db = import(
+            "era-genesis",
+            &[(&[], &state(0, &[], zero_checkpoints.clone())), (&blks, &state(8192, &roots, zero_checkpoints))],
+        )
+        .await;
+
+        assert_eq!(get::<data::Root>(db.db(), "block_0").as_deref(), Some(data::HEADER_GENESIS_ROOT));
+        let genesis_blk: data::Block = get(db.db(), &format!("block_{}", data::HEADER_GENESIS_ROOT)).unwrap();
+        assert_eq!((genesis_blk.slot, genesis_blk.state_root.as_str()), (0, data::GENESIS_STATE_ROOT));
+        assert!(db.db().get(format!("state_{}_committees", data::GENESIS_STATE_ROOT)).unwrap().is_some());
+        assert_eq!(get::<data::Root>(db.db(), "ebb_0_root").as_deref(), Some(data::HEADER_GENESIS_ROOT));
+        assert_eq!(get::<data::Root>(db.db(), "ebb_1_root"), Some(root(2)));
+        assert_eq!(get::<data::Block>(db.db(), &format!("block_{}", root(1))).unwrap().parent_root, data::HEADER_GENESIS_ROOT);
+        assert!((0..8192).all(|slot| db.db().get(format!("slot_{}_synched", slot)).unwrap().is_some()));
+        // epoch 1 refers to the genesis block as finalized checkpoint
+        replay(db.db(), 32, 200);
+    }
+
+    #[tokio::test]
+    async fn import_blocks_referenced_before_first_era() {
+        // the starting state finalizes epoch 254, and the epoch boundary block of the first
+        // imported epoch is the last block of the starting ERA file
+        let start_blks = blocks(&[8000, 8128, 8160, 8190], &root(7999));
+        let blks = blocks(&[8200, 8300, 9000], &root(8190));
+        let mut roots: Vec<(usize, data::Root)> = start_blks.iter().chain(&blks).map(|blk| (blk.slot, root(blk.slot))).collect();
+        roots.insert(0, (0, root(7999)));
+        let checkpoints = [checkpoint(254, &root(8128)), checkpoint(255, &root(8160)), checkpoint(254, &root(8128))];
+        let end_checkpoints = [checkpoint(255, &root(8160)), checkpoint(255, &root(8160)), checkpoint(254, &root(8128))];
+        let db = import(
+            "era-referenced",
+            &[(&start_blks, &state(8192, &roots, checkpoints)), (&blks, &state(16384, &roots, end_checkpoints))],
+        )
+        .await;
+
+        for slot in [8128, 8190] {
+            assert_eq!(get::<data::Block>(db.db(), &format!("block_{}", root(slot))).unwrap().slot, slot);
+            assert!(db.db().get(format!("slot_{}_synched", slot)).unwrap().is_none());
+        }
+        for slot in [8000, 8160] {
+            assert!(db.db().get(format!("block_{}", root(slot))).unwrap().is_none());
+        }
+        assert_eq!(get::<data::Root>(db.db(), "ebb_256_root"), Some(root(8190)));
+        replay(db.db(), 8192, 9100);
+    }
+}
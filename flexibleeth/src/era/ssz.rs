@@ -0,0 +1,487 @@
+use crate::data;
+use crate::shuffling::{self, Bytes32};
+
+pub const SLOTS_PER_HISTORICAL_ROOT: usize = 8192;
+
+// offsets of the fixed-size fields of BeaconState, which agree for all forks up to the
+// justification and finality checkpoints
+const STATE_SLOT: usize = 40;
+const STATE_FORK_CURRENT_VERSION: usize = 52;
+const STATE_BLOCK_ROOTS: usize = 176;
+const STATE_VALIDATORS: usize = 524552;
+const STATE_BALANCES: usize = 524556;
+const STATE_RANDAO_MIXES: usize = 524560;
+const STATE_JUSTIFICATION_BITS: usize = 2687256;
+const STATE_PREVIOUS_JUSTIFIED: usize = 2687257;
+const STATE_CURRENT_JUSTIFIED: usize = 2687297;
+const STATE_FINALIZED: usize = 2687337;
+const STATE_FIXED_PART_MIN_LENGTH: usize = 2687377;
+
+const VALIDATOR_LENGTH: usize = 121;
+const VALIDATOR_ACTIVATION_EPOCH: usize = 97;
+const VALIDATOR_EXIT_EPOCH: usize = 105;
+
+// offsets in BeaconBlockBody, which agree for all forks up to the attestations
+const BODY_ATTESTATIONS: usize = 208;
+const BODY_DEPOSITS: usize = 212;
+// offset in BeaconBlockBody from Bellatrix on
+const BODY_EXECUTION_PAYLOAD: usize = 380;
+
+// offsets in ExecutionPayload, which agree for all forks up to the block hash
+const EXECUTION_PAYLOAD_BLOCK_NUMBER: usize = 404;
+const EXECUTION_PAYLOAD_BLOCK_HASH: usize = 472;
+
+const ATTESTATION_DATA: usize = 4;
+const ATTESTATION_FIXED_PART_LENGTH: usize = 228;
+
+/// First fork whose blocks carry an execution payload
+pub const FORK_VERSION_BELLATRIX: u8 = 0x02;
+/// First fork whose attestations carry committee bits, which is not supported
+pub const FORK_VERSION_ELECTRA: u8 = 0x05;
+
+/// Fields of a BeaconState needed to import the ERA file that ends in it
+pub struct State {
+    pub slot: usize,
+    pub fork_version: [u8; 4],
+    pub block_roots: Vec<Bytes32>,
+    pub randao_mixes: Vec<Bytes32>,
+    pub validator_epochs: Vec<(usize, usize)>,   // activation epoch, exit epoch
+    pub justification_bits: u8,
+    pub previous_justified: data::Checkpoint,
+    pub current_justified: data::Checkpoint,
+    pub finalized: data::Checkpoint,
+}
+
+impl State {
+    /// Block root of the latest block at or before `slot`, which has to be one of the
+    /// `SLOTS_PER_HISTORICAL_ROOT` slots before the state
+    pub fn get_block_root_at_slot(&self, slot: usize) -> data::Root {
+        assert!(slot < self.slot && slot + SLOTS_PER_HISTORICAL_ROOT >= self.slot);
+        bytes_to_0xhex(&self.block_roots[slot % SLOTS_PER_HISTORICAL_ROOT])
+    }
+
+    pub fn get_active_validator_indices(&self, epoch: usize) -> Vec<usize> {
+        shuffling::get_active_validator_indices(&self.validator_epochs, epoch)
+    }
+
+    pub fn get_attester_seed(&self, epoch: usize) -> Bytes32 {
+        shuffling::get_attester_seed(epoch, &self.randao_mixes[shuffling::seed_randao_mix_index(epoch)])
+    }
+}
+
+pub fn bytes_to_0xhex(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+fn read_bytes(bytes: &[u8], offset: usize, length: usize) -> Result<&[u8], Box<dyn std::error::Error>> {
+    bytes
+        .get(offset..offset + length)
+        .ok_or_else(|| format!("Truncated SSZ: {} bytes at offset {} out of bounds", length, offset).into())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, Box<dyn std::error::Error>> {
+    Ok(u64::from_le_bytes(read_bytes(bytes, offset, 8)?.try_into().unwrap()))
+}
+
+fn read_offset(bytes: &[u8], offset: usize) -> Result<usize, Box<dyn std::error::Error>> {
+    Ok(u32::from_le_bytes(read_bytes(bytes, offset, 4)?.try_into().unwrap()) as usize)
+}
+
+fn read_root(bytes: &[u8], offset: usize) -> Result<data::Root, Box<dyn std::error::Error>> {
+    Ok(bytes_to_0xhex(read_bytes(bytes, offset, 32)?))
+}
+
+fn read_checkpoint(bytes: &[u8], offset: usize) -> Result<data::Checkpoint, Box<dyn std::error::Error>> {
+    Ok(data::Checkpoint {
+        epoch: read_u64(bytes, offset)? as usize,
+        root: read_root(bytes, offset + 8)?,
+    })
+}
+
+fn read_vector(bytes: &[u8], offset: usize, n: usize) -> Result<Vec<Bytes32>, Box<dyn std::error::Error>> {
+    Ok(read_bytes(bytes, offset, n * 32)?
+        .chunks_exact(32)
+        .map(|chunk| chunk.try_into().unwrap())
+        .collect())
+}
+
+/// Splits an SSZ list of variable-size elements into its elements
+fn read_variable_list(bytes: &[u8]) -> Result<Vec<&[u8]>, Box<dyn std::error::Error>> {
+    if bytes.is_empty() {
+        return Ok(vec![]);
+    }
+    let first_offset = read_offset(bytes, 0)?;
+    if first_offset % 4 != 0 || first_offset == 0 {
+        return Err(format!("Invalid SSZ list offset {}", first_offset).into());
+    }
+    let mut offsets = (0..first_offset / 4)
+        .map(|i| read_offset(bytes, 4 * i))
+        .collect::<Result<Vec<_>, _>>()?;
+    offsets.push(bytes.len());
+    offsets
+        .windows(2)
+        .map(|w| {
+            bytes
+                .get(w[0]..w[1])
+                .ok_or_else(|| format!("Invalid SSZ list offsets {}..{}", w[0], w[1]).into())
+        })
+        .collect()
+}
+
+pub fn decode_state(bytes: &[u8]) -> Result<State, Box<dyn std::error::Error>> {
+    if bytes.len() < STATE_FIXED_PART_MIN_LENGTH {
+        return Err("Truncated SSZ: state too short".into());
+    }
+    let validators_bytes = read_bytes(
+        bytes,
+        read_offset(bytes, STATE_VALIDATORS)?,
+        read_offset(bytes, STATE_BALANCES)?.saturating_sub(read_offset(bytes, STATE_VALIDATORS)?),
+    )?;
+    if validators_bytes.len() % VALIDATOR_LENGTH != 0 {
+        return Err("Invalid SSZ: validator registry length".into());
+    }
+    let validator_epochs = validators_bytes
+        .chunks_exact(VALIDATOR_LENGTH)
+        .map(|v| Ok((read_u64(v, VALIDATOR_ACTIVATION_EPOCH)? as usize, read_u64(v, VALIDATOR_EXIT_EPOCH)? as usize)))
+        .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+
+    Ok(State {
+        slot: read_u64(bytes, STATE_SLOT)? as usize,
+        fork_version: read_bytes(bytes, STATE_FORK_CURRENT_VERSION, 4)?.try_into().unwrap(),
+        block_roots: read_vector(bytes, STATE_BLOCK_ROOTS, SLOTS_PER_HISTORICAL_ROOT)?,
+        randao_mixes: read_vector(bytes, STATE_RANDAO_MIXES, shuffling::EPOCHS_PER_HISTORICAL_VECTOR)?,
+        validator_epochs,
+        justification_bits: bytes[STATE_JUSTIFICATION_BITS],
+        previous_justified: read_checkpoint(bytes, STATE_PREVIOUS_JUSTIFIED)?,
+        current_justified: read_checkpoint(bytes, STATE_CURRENT_JUSTIFIED)?,
+        finalized: read_checkpoint(bytes, STATE_FINALIZED)?,
+    })
+}
+
+fn read_block_body(message: &[u8]) -> Result<&[u8], Box<dyn std::error::Error>> {
+    message
+        .get(read_offset(message, 80)?..)
+        .ok_or_else(|| "Invalid SSZ: block body offset".into())
+}
+
+/// Decodes the execution block of a SignedBeaconBlock from Bellatrix on, which is None for
+/// the default execution payload of the blocks before the merge
+pub fn decode_signed_block_execution_payload(bytes: &[u8]) -> Result<Option<data::ExecutionPayload>, Box<dyn std::error::Error>> {
+    let message = bytes
+        .get(read_offset(bytes, 0)?..)
+        .ok_or("Invalid SSZ: block message offset")?;
+    let body = read_block_body(message)?;
+    let execution_payload = body
+        .get(read_offset(body, BODY_EXECUTION_PAYLOAD)?..)
+        .ok_or("Invalid SSZ: block execution payload offset")?;
+    let block_hash = read_root(execution_payload, EXECUTION_PAYLOAD_BLOCK_HASH)?;
+    if block_hash == data::PRE_MERGE_BLOCK_HASH {
+        return Ok(None);
+    }
+    Ok(Some(data::ExecutionPayload {
+        block_number: read_u64(execution_payload, EXECUTION_PAYLOAD_BLOCK_NUMBER)? as usize,
+        block_hash,
+    }))
+}
+
+/// Decodes a SignedBeaconBlock into a block without its root, which the ERA file does not
+/// store (the root is found in the block roots of the state at the end of the ERA file)
+pub fn decode_signed_block(bytes: &[u8]) -> Result<data::Block, Box<dyn std::error::Error>> {
+    let message = bytes
+        .get(read_offset(bytes, 0)?..)
+        .ok_or("Invalid SSZ: block message offset")?;
+    let body = read_block_body(message)?;
+    let attestations_bytes = body
+        .get(read_offset(body, BODY_ATTESTATIONS)?..read_offset(body, BODY_DEPOSITS)?)
+        .ok_or("Invalid SSZ: block attestations offsets")?;
+
+    let mut attestations = vec![];
+    for attestation in read_variable_list(attestations_bytes)? {
+        let aggregation_bits = attestation
+            .get(read_offset(attestation, 0)?..)
+            .filter(|_| attestation.len() >= ATTESTATION_FIXED_PART_LENGTH)
+            .ok_or("Invalid SSZ: attestation aggregation bits offset")?;
+        attestations.push(data::Attestation {
+            aggregation_bits: bytes_to_0xhex(aggregation_bits),
+            data: data::AttestationData {
+                slot: read_u64(attestation, ATTESTATION_DATA)? as usize,
+                index: read_u64(attestation, ATTESTATION_DATA + 8)? as usize,
+                beacon_block_root: read_root(attestation, ATTESTATION_DATA + 16)?,
+                source: read_checkpoint(attestation, ATTESTATION_DATA + 48)?,
+                target: read_checkpoint(attestation, ATTESTATION_DATA + 88)?,
+            },
+        });
+    }
+
+    Ok(data::Block {
+        slot: read_u64(message, 0)? as usize,
+        proposer_index: read_u64(message, 8)? as usize,
+        parent_root: read_root(message, 16)?,
+        state_root: read_root(message, 48)?,
+        body: data::BlockBody { attestations },
+    })
+}
+
+/// SSZ encoding of the containers that the decoder reads, following the field lists of the
+/// consensus specs (independently of the offsets above), for tests
+#[cfg(test)]
+pub mod encode {
+    use super::*;
+
+    pub enum Field {
+        Fixed(Vec<u8>),
+        Variable(Vec<u8>),
+    }
+    use Field::*;
+
+    /// Forks with different block bodies
+    pub enum Fork {
+        Phase0,
+        Altair,
+        Bellatrix(data::ExecutionPayload),
+    }
+
+    /// Serializes a container (or a list of variable-size elements): the fixed-size fields and
+    /// the offsets of the variable-size fields, followed by the variable-size fields
+    pub fn container(fields: Vec<Field>) -> Vec<u8> {
+        let fixed_length: usize = fields
+            .iter()
+            .map(|field| match field {
+                Fixed(bytes) => bytes.len(),
+                Variable(_) => 4,
+            })
+            .sum();
+        let (mut fixed, mut variable) = (vec![], vec![]);
+        for field in fields {
+            match field {
+                Fixed(bytes) => fixed.extend(bytes),
+                Variable(bytes) => {
+                    fixed.extend(((fixed_length + variable.len()) as u32).to_le_bytes());
+                    variable.extend(bytes);
+                }
+            }
+        }
+        fixed.extend(variable);
+        fixed
+    }
+
+    pub fn uint64(x: usize) -> Field {
+        Fixed((x as u64).to_le_bytes().to_vec())
+    }
+
+    pub fn zeros(length: usize) -> Field {
+        Fixed(vec![0; length])
+    }
+
+    pub fn root(root: &str) -> Field {
+        Fixed(hex::decode(root.trim_start_matches("0x")).unwrap())
+    }
+
+    pub fn checkpoint(checkpoint: &data::Checkpoint) -> Field {
+        Fixed(container(vec![uint64(checkpoint.epoch), root(&checkpoint.root)]))
+    }
+
+    pub fn attestation(attestation: &data::Attestation) -> Vec<u8> {
+        let attestation_data = container(vec![
+            uint64(attestation.data.slot),
+            uint64(attestation.data.index),
+            root(&attestation.data.beacon_block_root),
+            checkpoint(&attestation.data.source),
+            checkpoint(&attestation.data.target),
+        ]);
+        container(vec![
+            Variable(hex::decode(attestation.aggregation_bits.trim_start_matches("0x")).unwrap()),
+            Fixed(attestation_data),
+            zeros(96), // signature
+        ])
+    }
+
+    fn execution_payload(execution_payload: &data::ExecutionPayload) -> Vec<u8> {
+        container(vec![
+            zeros(32), // parent_hash
+            zeros(20), // fee_recipient
+            zeros(32), // state_root
+            zeros(32), // receipts_root
+            zeros(256), // logs_bloom
+            zeros(32), // prev_randao
+            uint64(execution_payload.block_number),
+            uint64(30_000_000), // gas_limit
+            uint64(15_000_000), // gas_used
+            uint64(1_700_000_000), // timestamp
+            Variable(b"extra".to_vec()), // extra_data
+            zeros(32), // base_fee_per_gas
+            root(&execution_payload.block_hash),
+            Variable(container(vec![Variable(vec![0x02; 40]), Variable(vec![0x02; 70])])), // transactions
+        ])
+    }
+
+    /// SignedBeaconBlock with the attestations of the block, a proposer slashing and a
+    /// voluntary exit around them
+    pub fn signed_block(blk: &data::Block, fork: &Fork) -> Vec<u8> {
+        let mut body = vec![
+            zeros(96), // randao_reveal
+            zeros(72), // eth1_data
+            Fixed(vec![0x67; 32]), // graffiti
+            Variable(vec![0x11; 416]), // proposer_slashings
+            Variable(vec![]), // attester_slashings
+            Variable(container(blk.body.attestations.iter().map(|a| Variable(attestation(a))).collect())),
+            Variable(vec![]), // deposits
+            Variable(vec![0x22; 112]), // voluntary_exits
+        ];
+        match fork {
+            Fork::Phase0 => {}
+            Fork::Altair => body.push(zeros(160)), // sync_aggregate
+            Fork::Bellatrix(payload) => {
+                body.push(zeros(160));
+                body.push(Variable(execution_payload(payload)));
+            }
+        }
+        let message = container(vec![
+            uint64(blk.slot),
+            uint64(blk.proposer_index),
+            root(&blk.parent_root),
+            root(&blk.state_root),
+            Variable(container(body)),
+        ]);
+        container(vec![Variable(message), zeros(96)])
+    }
+
+    /// Phase 0 BeaconState with the fields of the given state
+    pub fn state(state: &State) -> Vec<u8> {
+        let validators = state
+            .validator_epochs
+            .iter()
+            .flat_map(|(activation_epoch, exit_epoch)| {
+                container(vec![
+                    zeros(48), // pubkey
+                    zeros(32), // withdrawal_credentials
+                    uint64(32_000_000_000), // effective_balance
+                    zeros(1), // slashed
+                    uint64(0), // activation_eligibility_epoch
+                    uint64(*activation_epoch),
+                    uint64(*exit_epoch),
+                    uint64(usize::MAX), // withdrawable_epoch
+                ])
+            })
+            .collect();
+        let balances = state.validator_epochs.iter().flat_map(|_| 32_000_000_000u64.to_le_bytes()).collect();
+        container(vec![
+            uint64(1_606_824_023), // genesis_time
+            zeros(32), // genesis_validators_root
+            uint64(state.slot),
+            Fixed([state.fork_version, state.fork_version].concat()), // fork: previous and current version
+            uint64(0), // fork epoch
+            zeros(112), // latest_block_header
+            Fixed(state.block_roots.concat()),
+            zeros(32 * SLOTS_PER_HISTORICAL_ROOT), // state_roots
+            Variable(vec![]), // historical_roots
+            zeros(72), // eth1_data
+            Variable(vec![]), // eth1_data_votes
+            uint64(0), // eth1_deposit_index
+            Variable(validators),
+            Variable(balances),
+            Fixed(state.randao_mixes.concat()),
+            zeros(8 * 8192), // slashings
+            Variable(vec![]), // previous_epoch_attestations
+            Variable(vec![]), // current_epoch_attestations
+            Fixed(vec![state.justification_bits]),
+            checkpoint(&state.previous_justified),
+            checkpoint(&state.current_justified),
+            checkpoint(&state.finalized),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::encode::Fork;
+    use super::*;
+    use crate::testutil;
+
+    fn checkpoint(epoch: usize, tag: u8) -> data::Checkpoint {
+        data::Checkpoint { epoch, root: bytes_to_0xhex(&[tag; 32]) }
+    }
+
+    fn block() -> data::Block {
+        let attestation = |slot: usize, bits: &[bool]| data::Attestation {
+            aggregation_bits: testutil::bitlist(bits),
+            data: data::AttestationData {
+                slot,
+                index: slot % 3,
+                beacon_block_root: bytes_to_0xhex(&[slot as u8; 32]),
+                source: checkpoint(9, 0xa9),
+                target: checkpoint(10, 0xaa),
+            },
+        };
+        data::Block {
+            slot: 321,
+            proposer_index: 54321,
+            parent_root: bytes_to_0xhex(&[0xb1; 32]),
+            state_root: bytes_to_0xhex(&[0xc1; 32]),
+            body: data::BlockBody {
+                attestations: vec![
+                    attestation(320, &[true, false, true]),
+                    attestation(319, &[false; 130]),
+                    attestation(300, &[true; 9]),
+                ],
+            },
+        }
+    }
+
+    #[test]
+    fn decode_signed_block_of_every_fork() {
+        let execution_payload = data::ExecutionPayload { block_number: 17_000_123, block_hash: bytes_to_0xhex(&[0xe1; 32]) };
+        for fork in [Fork::Phase0, Fork::Altair, Fork::Bellatrix(execution_payload.clone())] {
+            let bytes = encode::signed_block(&block(), &fork);
+            assert_eq!(decode_signed_block(&bytes).unwrap(), block());
+            if let Fork::Bellatrix(_) = fork {
+                assert_eq!(decode_signed_block_execution_payload(&bytes).unwrap(), Some(execution_payload.clone()));
+            }
+        }
+
+        // default execution payload before the merge
+        let pre_merge = data::ExecutionPayload { block_number: 0, block_hash: data::PRE_MERGE_BLOCK_HASH.to_string() };
+        let bytes = encode::signed_block(&block(), &Fork::Bellatrix(pre_merge));
+        assert_eq!(decode_signed_block_execution_payload(&bytes).unwrap(), None);
+
+        // no attestations
+        let blk = data::Block { body: data::BlockBody { attestations: vec![] }, ..block() };
+        assert_eq!(decode_signed_block(&encode::signed_block(&blk, &Fork::Phase0)).unwrap(), blk);
+    }
+
+    #[test]
+    fn decode_truncated_signed_block_fails() {
+        let bytes = encode::signed_block(&block(), &Fork::Altair);
+        for length in [0, 3, 100, bytes.len() - 300] {
+            assert!(decode_signed_block(&bytes[..length]).is_err(), "length {}", length);
+        }
+    }
+
+    #[test]
+    fn decode_state_fields() {
+        let state = State {
+            slot: 6406144,
+            fork_version: [0x03, 0, 0, 0],
+            block_roots: (0..SLOTS_PER_HISTORICAL_ROOT).map(|i| [(i % 251) as u8; 32]).collect(),
+            randao_mixes: (0..shuffling::EPOCHS_PER_HISTORICAL_VECTOR).map(|i| [(i % 241) as u8; 32]).collect(),
+            validator_epochs: vec![(0, usize::MAX), (5, 300), (200000, usize::MAX)],
+            justification_bits: 0b0111,
+            previous_justified: checkpoint(200190, 0x01),
+            current_justified: checkpoint(200191, 0x02),
+            finalized: checkpoint(200189, 0x03),
+        };
+        let decoded = decode_state(&encode::state(&state)).unwrap();
+        assert_eq!(decoded.slot, state.slot);
+        assert_eq!(decoded.fork_version, state.fork_version);
+        assert_eq!(decoded.block_roots, state.block_roots);
+        assert_eq!(decoded.randao_mixes, state.randao_mixes);
+        assert_eq!(decoded.validator_epochs, state.validator_epochs);
+        assert_eq!(decoded.justification_bits, state.justification_bits);
+        assert_eq!(decoded.previous_justified, state.previous_justified);
+        assert_eq!(decoded.current_justified, state.current_justified);
+        assert_eq!(decoded.finalized, state.finalized);
+        assert_eq!(decoded.get_block_root_at_slot(6406143), bytes_to_0xhex(&[(8191 % 251) as u8; 32]));
+
+        assert!(decode_state(&encode::state(&state)[..STATE_FIXED_PART_MIN_LENGTH - 1]).is_err());
+    }
+}
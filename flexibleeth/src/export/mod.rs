@@ -0,0 +1,168 @@
+use rocksdb::{DB, Options};
+
+mod table;
+pub use table::Format;
+use table::{TableWriter, Value};
+use crate::cachedb::{get_canonical_block, get_decoded};
+use crate::data;
+use crate::utils;
+
+const BLOCKS_SCHEMA: &str = "
+message blocks {
+    REQUIRED INT64 slot;
+    REQUIRED BYTE_ARRAY root (UTF8);
+    REQUIRED INT64 proposer_index;
+    REQUIRED BYTE_ARRAY parent_root (UTF8);
+    REQUIRED BYTE_ARRAY state_root (UTF8);
+    REQUIRED INT64 attestation_count;
+}";
+
+const ATTESTATIONS_SCHEMA: &str = "
+message attestations {
+    REQUIRED INT64 block_slot;
+    REQUIRED BYTE_ARRAY block_root (UTF8);
+    REQUIRED INT64 slot;
+    REQUIRED INT64 index;
+    REQUIRED BYTE_ARRAY head_root (UTF8);
+    REQUIRED INT64 source_epoch;
+    REQUIRED BYTE_ARRAY source_root (UTF8);
+    REQUIRED INT64 target_epoch;
+    REQUIRED BYTE_ARRAY target_root (UTF8);
+    REQUIRED INT64 bit_count;
+}";
+
+const COMMITTEES_SCHEMA: &str = "
+message committees {
+    REQUIRED INT64 epoch;
+    REQUIRED INT64 slot;
+    REQUIRED INT64 index;
+    REQUIRED group validators (LIST) {
+        REPEATED group list {
+            REQUIRED INT64 element;
+        }
+    }
+}";
+
+const FINALITY_CHECKPOINTS_SCHEMA: &str = "
+message finality_checkpoints {
+    REQUIRED INT64 epoch;
+    REQUIRED BYTE_ARRAY state_root (UTF8);
+    REQUIRED INT64 previous_justified_epoch;
+    REQUIRED BYTE_ARRAY previous_justified_root (UTF8);
+    REQUIRED INT64 current_justified_epoch;
+    REQUIRED BYTE_ARRAY current_justified_root (UTF8);
+    REQUIRED INT64 finalized_epoch;
+    REQUIRED BYTE_ARRAY finalized_root (UTF8);
+}";
+
+pub async fn main(
+    db_path: String,
+    min_slot: usize,
+    max_slot: usize,
+    output_dir: String,
+    formats: Vec<Format>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut db_opts = Options::default();
+    db_opts.increase_parallelism(utils::get_available_cpucores() as i32);
+    db_opts.optimize_for_point_lookup(utils::get_available_ram() as u64 / 4);
+    let db = DB::open_for_read_only(&db_opts, db_path, true)?;
+
+    std::fs::create_dir_all(&output_dir)?;
+    let mut blocks = TableWriter::create(&output_dir, "blocks", BLOCKS_SCHEMA, &formats)?;
+    let mut attestations = TableWriter::create(&output_dir, "attestations", ATTESTATIONS_SCHEMA, &formats)?;
+    let mut committees = TableWriter::create(&output_dir, "committees", COMMITTEES_SCHEMA, &formats)?;
+    let mut finality_checkpoints =
+        TableWriter::create(&output_dir, "finality_checkpoints", FINALITY_CHECKPOINTS_SCHEMA, &formats)?;
+
+    log::info!("Exporting slots {}..={} to {}", min_slot, max_slot, output_dir);
+    let mut num_unsynched = 0;
+    let mut last_epoch_with_block = None;
+    for slot in min_slot..=max_slot {
+        if db.get(format!("slot_{}_synched", slot))?.is_none() {
+            num_unsynched += 1;
+            continue;
+        }
+        let (root, blk) = match get_canonical_block(&db, slot)? {
+            Some(block) => block,
+            None => continue, // empty slot
+        };
+
+        blocks.write(vec![
+            Value::Int(slot),
+            Value::Str(root.clone()),
+            Value::Int(blk.proposer_index),
+            Value::Str(blk.parent_root.clone()),
+            Value::Str(blk.state_root.clone()),
+            Value::Int(blk.body.attestations.len()),
+        ])?;
+        for attestation in blk.body.attestations {
+            // aggregation bits are an SSZ bitlist, whose highest set bit delimits its length
+            let bit_count = utils::AggregationBits::new_from_0xhex_str(&attestation.aggregation_bits).count();
+            attestations.write(vec![
+                Value::Int(slot),
+                Value::Str(root.clone()),
+                Value::Int(attestation.data.slot),
+                Value::Int(attestation.data.index),
+                Value::Str(attestation.data.beacon_block_root),
+                Value::Int(attestation.data.source.epoch),
+                Value::Str(attestation.data.source.root),
+                Value::Int(attestation.data.target.epoch),
+                Value::Str(attestation.data.target.root),
+                Value::Int(bit_count.saturating_sub(1)),
+            ])?;
+        }
+
+        // committees and finality checkpoints from the state of the first block of an epoch
+        let epoch = utils::slot_to_epoch(slot);
+        if last_epoch_with_block == Some(epoch) {
+            continue;
+        }
+        last_epoch_with_block = Some(epoch);
+        let state_committees = get_decoded::<Vec<data::CommitteeAssignment>>(&db, &format!("state_{}_committees", blk.state_root))?;
+        let state_checkpoints = get_decoded::<(data::Checkpoint, data::Checkpoint, data::Checkpoint)>(
+            &db,
+            &format!("state_{}_finality_checkpoints", blk.state_root),
+        )?;
+        let (state_committees, (cp_previous_justified, cp_current_justified, cp_finalized)) =
+            match (state_committees, state_checkpoints) {
+                (Some(state_committees), Some(state_checkpoints)) => (state_committees, state_checkpoints),
+                _ => {
+                    log::warn!(
+                        "State data of epoch {} not found at block {} (slot {}), not exporting its committees and finality checkpoints",
+                        epoch,
+                        root,
+                        slot
+                    );
+                    continue;
+                }
+            };
+        for committee in state_committees {
+            committees.write(vec![
+                Value::Int(epoch),
+                Value::Int(committee.slot),
+                Value::Int(committee.index),
+                Value::IntList(committee.validators),
+            ])?;
+        }
+        finality_checkpoints.write(vec![
+            Value::Int(epoch),
+            Value::Str(blk.state_root),
+            Value::Int(cp_previous_justified.epoch),
+            Value::Str(cp_previous_justified.root),
+            Value::Int(cp_current_justified.epoch),
+            Value::Str(cp_current_justified.root),
+            Value::Int(cp_finalized.epoch),
+            Value::Str(cp_finalized.root),
+        ])?;
+    }
+
+    blocks.close()?;
+    attestations.close()?;
+    committees.close()?;
+    finality_checkpoints.close()?;
+    if num_unsynched > 0 {
+        log::warn!("{} slots in {}..={} not synched and not exported", num_unsynched, min_slot, max_slot);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,207 @@
+use parquet::basic::{Compression, Type as PhysicalType};
+use parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::Arc;
+
+/// Output file format
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Format {
+    /// Apache Parquet (Snappy-compressed)
+    Parquet,
+    /// One JSON object per line
+    Jsonl,
+}
+
+/// Value of a column in a row
+pub enum Value {
+    Int(usize),
+    Str(String),
+    IntList(Vec<usize>),
+}
+
+impl Value {
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Value::Int(v) => serde_json::Value::from(*v),
+            Value::Str(v) => serde_json::Value::from(v.as_str()),
+            Value::IntList(v) => serde_json::Value::from(v.clone()),
+        }
+    }
+}
+
+/// Values of a Parquet leaf column buffered for the next row group
+enum Column {
+    Int(Vec<i64>),
+    Str(Vec<ByteArray>),
+    IntList { values: Vec<i64>, def_levels: Vec<i16>, rep_levels: Vec<i16> },
+}
+
+impl Column {
+    fn push(&mut self, value: Value) -> Result<(), Box<dyn std::error::Error>> {
+        match (self, value) {
+            (Column::Int(values), Value::Int(v)) => values.push(v as i64),
+            (Column::Str(values), Value::Str(v)) => values.push(ByteArray::from(v.as_str())),
+            (Column::IntList { values, def_levels, rep_levels }, Value::IntList(v)) => {
+                if v.is_empty() {
+                    def_levels.push(0);
+                    rep_levels.push(0);
+                }
+                for (i, element) in v.into_iter().enumerate() {
+                    values.push(element as i64);
+                    def_levels.push(1);
+                    rep_levels.push(if i == 0 { 0 } else { 1 });
+                }
+            }
+            _ => return Err("Value does not match column type".into()),
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Column::Int(values) => values.len(),
+            Column::Str(values) => values.len(),
+            Column::IntList { def_levels, .. } => def_levels.len(),
+        }
+    }
+
+    fn clear(&mut self) {
+        match self {
+            Column::Int(values) => values.clear(),
+            Column::Str(values) => values.clear(),
+            Column::IntList { values, def_levels, rep_levels } => {
+                values.clear();
+                def_levels.clear();
+                rep_levels.clear();
+            }
+        }
+    }
+}
+
+/// Buffered values after which a row group is written
+const ROW_GROUP_VALUES: usize = 1 << 22;
+
+struct ParquetWriter {
+    writer: SerializedFileWriter<File>,
+    columns: Vec<Column>,
+}
+
+impl ParquetWriter {
+    fn write_row_group(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.columns.iter().all(|column| column.len() == 0) {
+            return Ok(());
+        }
+        let mut row_group_writer = self.writer.next_row_group()?;
+        for column in self.columns.iter_mut() {
+            let mut column_writer = row_group_writer.next_column()?.ok_or("Fewer Parquet columns than in schema")?;
+            match column {
+                Column::Int(values) => {
+                    column_writer.typed::<Int64Type>().write_batch(values, None, None)?;
+                }
+                Column::Str(values) => {
+                    column_writer.typed::<ByteArrayType>().write_batch(values, None, None)?;
+                }
+                Column::IntList { values, def_levels, rep_levels } => {
+                    column_writer
+                        .typed::<Int64Type>()
+                        .write_batch(values, Some(def_levels), Some(rep_levels))?;
+                }
+            }
+            column_writer.close()?;
+            column.clear();
+        }
+        row_group_writer.close()?;
+        Ok(())
+    }
+}
+
+/// Writer of the rows of a table to a file per format (`<dir>/<name>.<format>`), given the
+/// Parquet schema of the table, whose top-level fields are the columns of the rows
+pub struct TableWriter {
+    path: String,
+    column_names: Vec<String>,
+    jsonl: Option<BufWriter<File>>,
+    parquet: Option<ParquetWriter>,
+    rows: usize,
+}
+
+impl TableWriter {
+    pub fn create(dir: &str, name: &str, schema: &str, formats: &[Format]) -> Result<Self, Box<dyn std::error::Error>> {
+        let schema = Arc::new(parse_message_type(schema)?);
+        let column_names = schema.get_fields().iter().map(|field| field.name().to_string()).collect();
+        let path = format!("{}/{}", dir, name);
+
+        let jsonl = match formats.contains(&Format::Jsonl) {
+            true => Some(BufWriter::new(File::create(format!("{}.jsonl", path))?)),
+            false => None,
+        };
+        let parquet = match formats.contains(&Format::Parquet) {
+            true => {
+                let props = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+                let writer = SerializedFileWriter::new(File::create(format!("{}.parquet", path))?, schema, Arc::new(props))?;
+                let columns = writer
+                    .schema_descr()
+                    .columns()
+                    .iter()
+                    .map(|column| match (column.physical_type(), column.max_rep_level()) {
+                        (PhysicalType::INT64, 0) => Ok(Column::Int(vec![])),
+                        (PhysicalType::INT64, 1) => Ok(Column::IntList { values: vec![], def_levels: vec![], rep_levels: vec![] }),
+                        (PhysicalType::BYTE_ARRAY, 0) => Ok(Column::Str(vec![])),
+                        _ => Err(format!("Unsupported column {} in schema of {}", column.path(), name)),
+                    })
+                    .collect::<Result<_, _>>()?;
+                Some(ParquetWriter { writer, columns })
+            }
+            false => None,
+        };
+
+        Ok(Self { path, column_names, jsonl, parquet, rows: 0 })
+    }
+
+    /// Writes a row, with values in the order of the columns of the schema
+    pub fn write(&mut self, row: Vec<Value>) -> Result<(), Box<dyn std::error::Error>> {
+        if row.len() != self.column_names.len() {
+            return Err(format!("Row with {} values for {} columns of {}", row.len(), self.column_names.len(), self.path).into());
+        }
+        if let Some(jsonl) = &mut self.jsonl {
+            // fields in the order of the columns
+            jsonl.write_all(b"{")?;
+            for (i, (name, value)) in self.column_names.iter().zip(&row).enumerate() {
+                if i > 0 {
+                    jsonl.write_all(b",")?;
+                }
+                serde_json::to_writer(&mut *jsonl, name)?;
+                jsonl.write_all(b":")?;
+                serde_json::to_writer(&mut *jsonl, &value.to_json())?;
+            }
+            jsonl.write_all(b"}\n")?;
+        }
+        if let Some(parquet) = &mut self.parquet {
+            for (column, value) in parquet.columns.iter_mut().zip(row) {
+                column.push(value)?;
+            }
+            if parquet.columns.iter().map(|column| column.len()).sum::<usize>() >= ROW_GROUP_VALUES {
+                parquet.write_row_group()?;
+            }
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Finishes the files and returns the number of rows written
+    pub fn close(self) -> Result<usize, Box<dyn std::error::Error>> {
+        if let Some(mut jsonl) = self.jsonl {
+            jsonl.flush()?;
+        }
+        if let Some(mut parquet) = self.parquet {
+            parquet.write_row_group()?;
+            parquet.writer.close()?;
+        }
+        log::info!("Wrote {} rows to {}", self.rows, self.path);
+        Ok(self.rows)
+    }
+}
@@ -0,0 +1,107 @@
+use rocksdb::{DBCompressionType, DB, Options};
+
+use crate::data;
+use crate::utils;
+
+/// Copies keys from the caching database to the extracted database
+struct Extractor<'a> {
+    db: &'a DB,
+    out: DB,
+    num_keys: usize,
+    num_bytes: usize,
+}
+
+impl Extractor<'_> {
+    fn copy(&mut self, key: &str) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+        if let Some(value) = self.out.get(key)? {
+            // already copied
+            return Ok(Some(value));
+        }
+        let value = self.db.get(key)?;
+        if let Some(value) = &value {
+            self.out.put(key, value)?;
+            self.num_keys += 1;
+            self.num_bytes += key.len() + value.len();
+        }
+        Ok(value)
+    }
+
+    fn copy_required(&mut self, key: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        self.copy(key)?
+            .ok_or_else(|| format!("{} not found in caching database", key).into())
+    }
+}
+
+pub async fn main(db_path: String, min_slot: usize, max_slot: usize, out_path: String) -> Result<(), Box<dyn std::error::Error>> {
+    let mut db_opts = Options::default();
+    db_opts.increase_parallelism(utils::get_available_cpucores() as i32);
+    db_opts.optimize_for_point_lookup(utils::get_available_ram() as u64 / 4);
+    let db = DB::open_for_read_only(&db_opts, db_path, true)?;
+
+    let mut out_opts = Options::default();
+    out_opts.create_if_missing(true);
+    out_opts.set_error_if_exists(true);
+    out_opts.set_compression_type(DBCompressionType::Zstd);
+    let mut extractor = Extractor { db: &db, out: DB::open(&out_opts, &out_path)?, num_keys: 0, num_bytes: 0 };
+
+    // keys read by the confirmation rule when replaying the range (see `confrule::replay`)
+    log::info!("Extracting slots {}..={} to {}", min_slot, max_slot, out_path);
+    let mut last_registered_epoch = utils::slot_to_epoch(min_slot);
+    let mut min_finalized_slot = min_slot;
+    for slot in min_slot..=max_slot {
+        if extractor.copy(&format!("slot_{}_synched", slot))?.is_none() {
+            return Err(format!("Slot {} not synched (see `status`)", slot).into());
+        }
+        let blkroot = match extractor.copy(&format!("block_{}", slot))? {
+            Some(serialized_blkroot) => bincode::deserialize::<data::Root>(&serialized_blkroot)?,
+            None => continue, // empty slot
+        };
+        let blk = bincode::deserialize::<data::Block>(&extractor.copy_required(&format!("block_{}", blkroot))?)?;
+        extractor.copy(&format!("block_{}_execution_payload", blkroot))?;
+
+        // first block of an epoch registers a confirmation target
+        let epoch = utils::slot_to_epoch(slot);
+        if epoch <= last_registered_epoch {
+            continue;
+        }
+        last_registered_epoch = epoch;
+        extractor.copy(&format!("epoch_{}_state_synched", epoch))?;
+        extractor.copy_required(&format!("state_{}_committees", blk.state_root))?;
+        let (_cp_previous_justified, _cp_current_justified, cp_finalized) =
+            bincode::deserialize::<(data::Checkpoint, data::Checkpoint, data::Checkpoint)>(
+                &extractor.copy_required(&format!("state_{}_finality_checkpoints", blk.state_root))?,
+            )?;
+        let mut cp_finalized_blkroot = cp_finalized.root;
+        if cp_finalized_blkroot == "0x0000000000000000000000000000000000000000000000000000000000000000" {
+            cp_finalized_blkroot = data::HEADER_GENESIS_ROOT.to_string();
+        }
+        // the finalized block may lie before the range
+        let cp_finalized_blk =
+            bincode::deserialize::<data::Block>(&extractor.copy_required(&format!("block_{}", cp_finalized_blkroot))?)?;
+        min_finalized_slot = std::cmp::min(min_finalized_slot, cp_finalized_blk.slot);
+        let ebb_root = bincode::deserialize::<data::Root>(&extractor.copy_required(&format!("ebb_{}_root", epoch))?)?;
+        // the epoch boundary block may lie before the range
+        extractor.copy_required(&format!("block_{}", ebb_root))?;
+    }
+
+    // canonical block roots from the earliest confirmation target on, for confirmation latencies,
+    // and the execution blocks of the confirmed tips among them
+    for slot in min_finalized_slot..min_slot {
+        if let Some(serialized_blkroot) = extractor.copy(&format!("block_{}", slot))? {
+            let blkroot = bincode::deserialize::<data::Root>(&serialized_blkroot)?;
+            extractor.copy(&format!("block_{}_execution_payload", blkroot))?;
+        }
+    }
+
+    extractor.out.compact_range(None::<&[u8]>, None::<&[u8]>);
+    log::info!(
+        "Extracted {} keys ({} bytes) for slots {}..={} to {}",
+        extractor.num_keys,
+        extractor.num_bytes,
+        min_slot,
+        max_slot,
+        out_path
+    );
+
+    Ok(())
+}
@@ -0,0 +1,253 @@
+use rocksdb::{DB, Options};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::cachedb::{get_block, get_decoded};
+use crate::data;
+use crate::utils;
+
+/// Inclusion delays (in slots) for which the share of votes for the confirmation target
+/// included within the delay is reported
+const DELAY_THRESHOLDS: [usize; 4] = [1, 2, 4, 32];
+
+/// Vote of a committee member, at its first inclusion
+struct Vote {
+    committee: (usize, usize),  // slot, index
+    target: data::Root,
+    delay: usize,
+}
+
+/// Votes of the attestations of one epoch, by committee and position in the committee
+#[derive(Default)]
+struct EpochVotes {
+    votes: HashMap<(usize, usize, usize), Vote>,
+}
+
+impl EpochVotes {
+    fn add_attestation(&mut self, attestation: &data::Attestation, inclusion_slot: usize) {
+        let aggregation_bits = utils::AggregationBits::new_from_0xhex_str(&attestation.aggregation_bits);
+        for position in aggregation_bits.get_vote_positions() {
+            self.votes
+                .entry((attestation.data.slot, attestation.data.index, position))
+                .or_insert_with(|| Vote {
+                    committee: (attestation.data.slot, attestation.data.index),
+                    target: attestation.data.target.root.clone(),
+                    delay: inclusion_slot - attestation.data.slot,
+                });
+        }
+    }
+}
+
+/// Mean, median, 90th percentile (nearest rank) and maximum of inclusion delays
+fn format_statistics(delays: &mut [usize]) -> String {
+    if delays.is_empty() {
+        return "mean=NA p50=NA p90=NA max=NA".to_string();
+    }
+    delays.sort_unstable();
+    let percentile = |p: f64| delays[((p * delays.len() as f64).ceil() as usize).max(1) - 1];
+    format!(
+        "mean={:.2} p50={} p90={} max={}",
+        delays.iter().sum::<usize>() as f64 / delays.len() as f64,
+        percentile(0.5),
+        percentile(0.9),
+        delays[delays.len() - 1]
+    )
+}
+
+/// Share of the delays within each of the thresholds
+fn format_shares_within(delays: &[usize]) -> String {
+    DELAY_THRESHOLDS
+        .iter()
+        .map(|threshold| match delays.is_empty() {
+            true => format!("within_{}=NA", threshold),
+            false => format!(
+                "within_{}={:.4}",
+                threshold,
+                delays.iter().filter(|d| *d <= threshold).count() as f64 / delays.len() as f64
+            ),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Removes the epochs whose votes can no longer be included from the first slot of an epoch on,
+/// i.e., those at least two epochs before it
+fn take_done_epochs(epochs: &mut BTreeMap<usize, EpochVotes>, epoch: usize) -> Vec<(usize, EpochVotes)> {
+    let mut done_epochs = vec![];
+    while let Some(entry) = epochs.first_entry() {
+        if *entry.key() + 2 > epoch {
+            break;
+        }
+        done_epochs.push(entry.remove_entry());
+    }
+    done_epochs
+}
+
+fn print_epoch(db: &DB, epoch: usize, epoch_votes: EpochVotes, per_committee: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let ebb_root = get_decoded::<data::Root>(db, &format!("ebb_{}_root", epoch))?
+        .ok_or_else(|| format!("EBB root of epoch {} not found", epoch))?;
+
+    let mut delays: Vec<usize> = epoch_votes.votes.values().map(|vote| vote.delay).collect();
+    // votes for the epoch boundary block are the ones the confirmation rule counts
+    let mut target_delays: BTreeMap<&data::Root, Vec<usize>> = BTreeMap::new();
+    let mut committee_delays: BTreeMap<(usize, usize), Vec<usize>> = BTreeMap::new();
+    for vote in epoch_votes.votes.values() {
+        target_delays.entry(&vote.target).or_default().push(vote.delay);
+        committee_delays.entry(vote.committee).or_default().push(vote.delay);
+    }
+    let quorum_relevant_delays = target_delays.get(&ebb_root).cloned().unwrap_or_default();
+
+    println!(
+        "INCLUSION_DELAY epoch={} votes={} {} target_votes={} {}",
+        epoch,
+        delays.len(),
+        format_statistics(&mut delays),
+        quorum_relevant_delays.len(),
+        format_shares_within(&quorum_relevant_delays)
+    );
+    for (target, mut delays) in target_delays {
+        println!(
+            "INCLUSION_DELAY_TARGET epoch={} target={} canonical={} votes={} {} {}",
+            epoch,
+            target,
+            *target == ebb_root,
+            delays.len(),
+            format_statistics(&mut delays),
+            format_shares_within(&delays)
+        );
+    }
+    if per_committee {
+        for ((slot, index), mut delays) in committee_delays {
+            println!(
+                "INCLUSION_DELAY_COMMITTEE epoch={} slot={} index={} votes={} {}",
+                epoch,
+                slot,
+                index,
+                delays.len(),
+                format_statistics(&mut delays)
+            );
+        }
+    }
+    Ok(())
+}
+
+pub async fn main(db_path: String, min_slot: usize, max_slot: usize, per_committee: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut db_opts = Options::default();
+    db_opts.increase_parallelism(utils::get_available_cpucores() as i32);
+    db_opts.optimize_for_point_lookup(utils::get_available_ram() as u64 / 4);
+    let db = DB::open_for_read_only(&db_opts, db_path, true)?;
+
+    // votes of an epoch are included until the end of the following epoch
+    let min_epoch = utils::slot_to_epoch(min_slot);
+    let max_epoch = utils::slot_to_epoch(max_slot);
+    let mut epochs: BTreeMap<usize, EpochVotes> = BTreeMap::new();
+    for slot in utils::epoch_to_slot(min_epoch)..utils::epoch_to_slot(max_epoch + 2) {
+        // report epochs whose votes can no longer be included
+        if utils::is_epoch_boundary_slot(slot) {
+            for (done_epoch, epoch_votes) in take_done_epochs(&mut epochs, utils::slot_to_epoch(slot)) {
+                print_epoch(&db, done_epoch, epoch_votes, per_committee)?;
+            }
+        }
+
+        let blk = match get_block(&db, slot)? {
+            Some(blk) => blk,
+            None => continue, // empty slot
+        };
+        for attestation in &blk.body.attestations {
+            let epoch = utils::slot_to_epoch(attestation.data.slot);
+            if epoch < min_epoch || epoch > max_epoch {
+                continue;
+            }
+            epochs.entry(epoch).or_default().add_attestation(attestation, blk.slot);
+        }
+    }
+    for (epoch, epoch_votes) in epochs {
+        print_epoch(&db, epoch, epoch_votes, per_committee)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    fn attestation(slot: usize, index: usize, bits: &[bool], target: &str) -> data::Attestation {
+        let checkpoint = data::Checkpoint { epoch: utils::slot_to_epoch(slot), root: target.to_string() };
+        data::Attestation {
+            aggregation_bits: testutil::bitlist(bits),
+            data: data::AttestationData {
+                slot,
+                index,
+                beacon_block_root: target.to_string(),
+                source: checkpoint.clone(),
+                target: checkpoint,
+            },
+        }
+    }
+
+    fn delays(epoch_votes: &EpochVotes) -> BTreeMap<(usize, usize, usize), (usize, &str)> {
+        epoch_votes.votes.iter().map(|(key, vote)| (*key, (vote.delay, vote.target.as_str()))).collect()
+    }
+
+    #[test]
+    fn first_inclusion_of_a_vote_counts() {
+        let mut epoch_votes = EpochVotes::default();
+        epoch_votes.add_attestation(&attestation(10, 0, &[true, true, false], "0xaa"), 12);
+        // repeated in a later aggregate, with a vote for another target
+        epoch_votes.add_attestation(&attestation(10, 0, &[false, true, true], "0xbb"), 15);
+        epoch_votes.add_attestation(&attestation(10, 1, &[true, false, false], "0xaa"), 11);
+        assert_eq!(
+            delays(&epoch_votes),
+            BTreeMap::from([
+                ((10, 0, 0), (2, "0xaa")),
+                ((10, 0, 1), (2, "0xaa")),
+                ((10, 0, 2), (5, "0xbb")),
+                ((10, 1, 0), (1, "0xaa")),
+            ])
+        );
+    }
+
+    #[test]
+    fn delimiter_bit_is_not_a_vote() {
+        let mut epoch_votes = EpochVotes::default();
+        epoch_votes.add_attestation(&attestation(10, 0, &[false; 8], "0xaa"), 11);
+        epoch_votes.add_attestation(&attestation(10, 1, &[false, false, false], "0xaa"), 11);
+        assert!(epoch_votes.votes.is_empty());
+        // a full byte of votes, delimited in the next byte
+        epoch_votes.add_attestation(&attestation(10, 0, &[true; 8], "0xaa"), 11);
+        assert_eq!(epoch_votes.votes.len(), 8);
+        assert!(epoch_votes.votes.keys().all(|(_, _, position)| *position < 8));
+    }
+
+    #[test]
+    fn statistics_use_nearest_rank_percentiles() {
+        assert_eq!(format_statistics(&mut []), "mean=NA p50=NA p90=NA max=NA");
+        assert_eq!(format_statistics(&mut [3]), "mean=3.00 p50=3 p90=3 max=3");
+        assert_eq!(format_statistics(&mut [2, 1]), "mean=1.50 p50=1 p90=2 max=2");
+        assert_eq!(format_statistics(&mut [10, 9, 8, 7, 6, 5, 4, 3, 2, 1]), "mean=5.50 p50=5 p90=9 max=10");
+        assert_eq!(format_statistics(&mut [1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 32]), "mean=3.82 p50=1 p90=1 max=32");
+    }
+
+    #[test]
+    fn shares_within_include_threshold_delays() {
+        assert_eq!(format_shares_within(&[]), "within_1=NA within_2=NA within_4=NA within_32=NA");
+        assert_eq!(
+            format_shares_within(&[1, 2, 3, 4, 5, 32, 33, 1]),
+            "within_1=0.2500 within_2=0.3750 within_4=0.6250 within_32=0.8750"
+        );
+    }
+
+    #[test]
+    fn epochs_are_done_at_the_boundary_two_epochs_later() {
+        let mut epochs: BTreeMap<usize, EpochVotes> = (3..=5).map(|epoch| (epoch, EpochVotes::default())).collect();
+        let done = |epochs: &mut BTreeMap<usize, EpochVotes>, epoch| {
+            take_done_epochs(epochs, epoch).into_iter().map(|(done_epoch, _)| done_epoch).collect::<Vec<_>>()
+        };
+        assert!(done(&mut epochs, 4).is_empty());
+        assert_eq!(done(&mut epochs, 5), vec![3]);
+        assert!(done(&mut epochs, 5).is_empty());
+        assert_eq!(done(&mut epochs, 8), vec![4, 5]);
+        assert!(epochs.is_empty());
+    }
+}
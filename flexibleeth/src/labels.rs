@@ -0,0 +1,78 @@
+use std::collections::{BTreeSet, HashMap};
+
+/// Lines of a text file, without comments (`#` to the end of the line) and blank lines
+fn read_lines(path: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let content = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect())
+}
+
+/// Loads validator indices from a file, one per line
+pub fn load_validator_indices(path: &str) -> Result<Vec<usize>, Box<dyn std::error::Error>> {
+    read_lines(path)?
+        .iter()
+        .map(|line| line.parse().map_err(|e| format!("{}: {}: {}", path, line, e).into()))
+        .collect()
+}
+
+/// Loads operator labels from a CSV file with lines `validator_index,operator` (a header
+/// line `validator_index,operator` is skipped), or from a JSON file (`.json`) with an object
+/// mapping validator indices to operators
+pub fn load_operator_labels(path: &str) -> Result<HashMap<usize, String>, Box<dyn std::error::Error>> {
+    if path.ends_with(".json") {
+        let content = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let labels: HashMap<String, String> = serde_json::from_str(&content).map_err(|e| format!("{}: {}", path, e))?;
+        return labels
+            .into_iter()
+            .map(|(validator, operator)| match validator.parse() {
+                Ok(validator) => Ok((validator, operator)),
+                Err(e) => Err(format!("{}: {}: {}", path, validator, e).into()),
+            })
+            .collect();
+    }
+
+    let mut labels = HashMap::new();
+    for line in read_lines(path)? {
+        let (validator, operator) = line
+            .split_once(',')
+            .ok_or_else(|| format!("{}: {}: expected validator_index,operator", path, line))?;
+        if validator.trim() == "validator_index" {
+            continue;
+        }
+        let validator = validator.trim().parse().map_err(|e| format!("{}: {}: {}", path, line, e))?;
+        labels.insert(validator, operator.trim().to_string());
+    }
+    Ok(labels)
+}
+
+/// Validators given by index, in index files, or by operator in an operator label file
+pub fn resolve_validators(
+    indices: Vec<usize>,
+    index_paths: &[String],
+    operator_labels_path: Option<&str>,
+    operators: &[String],
+) -> Result<BTreeSet<usize>, Box<dyn std::error::Error>> {
+    let mut validators: BTreeSet<usize> = indices.into_iter().collect();
+    for path in index_paths {
+        validators.extend(load_validator_indices(path)?);
+    }
+    if !operators.is_empty() {
+        let labels = load_operator_labels(operator_labels_path.ok_or("Operators given without operator labels")?)?;
+        for operator in operators {
+            let operator_validators: Vec<usize> = labels
+                .iter()
+                .filter(|(_, label)| *label == operator)
+                .map(|(validator, _)| *validator)
+                .collect();
+            if operator_validators.is_empty() {
+                return Err(format!("No validators labeled with operator {}", operator).into());
+            }
+            log::info!("Operator {}: {} validators", operator, operator_validators.len());
+            validators.extend(operator_validators);
+        }
+    }
+    Ok(validators)
+}
@@ -2,9 +2,23 @@ use clap::{Parser, Subcommand};
 use ratelimit::Ratelimiter;
 use std::time::Duration;
 
+mod cachedb;
+mod checkdb;
 mod confrule;
 mod data;
+mod entities;
+mod era;
+mod export;
+mod extract;
+mod inclusion;
+mod labels;
+mod metrics;
+mod service;
+mod shuffling;
+mod status;
 mod sync;
+#[cfg(test)]
+mod testutil;
 mod utils;
 
 #[derive(Parser)]
@@ -26,17 +40,36 @@ enum Commands {
         #[arg(long, default_value = "cache.rocksdb")]
         db_path: String,
 
-        /// Beacon chain RPC endpoint URL
-        #[arg(long, default_value = "https://lodestar-mainnet.chainsafe.io")]
-        rpc_url: String,
+        /// Beacon chain RPC endpoint URL (repeat to load-balance and fail over across several endpoints)
+        #[arg(long = "rpc-url", default_value = "https://lodestar-mainnet.chainsafe.io")]
+        rpc_urls: Vec<String>,
+
+        #[command(flatten)]
+        client_options: crate::sync::api::ClientOptions,
 
         /// Minimum slot to synchronize
         #[arg(long, default_value = "0")]
         min_slot: usize,
 
         /// Maximum slot to synchronize
+        #[arg(long, required_unless_present = "fill_gaps")]
+        max_slot: Option<usize>,
+
+        /// Only synchronize the gaps between slots already synched (and epochs missing state), within minimum and maximum slot if given
         #[arg(long)]
-        max_slot: usize,
+        fill_gaps: bool,
+
+        /// Genesis time (unix time) used to determine the current slot
+        #[arg(long, default_value_t = crate::utils::GENESIS_TIME)]
+        genesis_time: u64,
+
+        /// Where to obtain the committees of epochs from
+        #[arg(long, value_enum, default_value = "rpc")]
+        committees: crate::sync::CommitteeSource,
+
+        /// State whose validator registry and RANDAO mixes are used to compute committees locally (slot or state identifier, at or after the synchronized slots)
+        #[arg(long, default_value = "head")]
+        committees_state_id: String,
 
         /// Rate limit for beacon chain RPC endpoint: requests (numerator)
         #[arg(long, default_value_t = 10)]
@@ -45,6 +78,131 @@ enum Commands {
         /// Rate limit for beacon chain RPC endpoint: seconds (denominator)
         #[arg(long, default_value_t = 1.0)]
         rl_seconds: f64,
+
+        /// Address to serve Prometheus metrics on (disabled if not given)
+        #[arg(long)]
+        metrics_listen: Option<std::net::SocketAddr>,
+    },
+
+    /// Import consensus metadata (blocks, votes, ...) from ERA archive files to caching database
+    ImportEra {
+        /// Caching database path
+        #[arg(long, default_value = "cache.rocksdb")]
+        db_path: String,
+
+        /// ERA files of consecutive eras (the first era only provides the starting state)
+        #[arg(required = true)]
+        era_files: Vec<String>,
+    },
+
+    /// Show synched slot ranges, gaps and epochs missing state in caching database
+    Status {
+        /// Caching database path
+        #[arg(long, default_value = "cache.rocksdb")]
+        db_path: String,
+    },
+
+    /// Export blocks, attestations, committees and finality checkpoints from caching database to Parquet or JSONL files
+    Export {
+        /// Caching database path
+        #[arg(long, default_value = "cache.rocksdb")]
+        db_path: String,
+
+        /// Minimum slot to export
+        #[arg(long, default_value = "0")]
+        min_slot: usize,
+
+        /// Maximum slot to export
+        #[arg(long)]
+        max_slot: usize,
+
+        /// Output directory (one file per table and format)
+        #[arg(long)]
+        output_dir: String,
+
+        /// Output format (repeat to write several formats)
+        #[arg(long = "format", value_enum, default_value = "parquet")]
+        formats: Vec<crate::export::Format>,
+    },
+
+    /// Report per-operator votes for every confirmation target and the minimum number of operators needed to reach each quorum
+    EntityVotes {
+        /// Caching database path
+        #[arg(long, default_value = "cache.rocksdb")]
+        db_path: String,
+
+        /// Minimum slot (confirmation targets of the epochs from the one of this slot on)
+        #[arg(long, default_value = "0")]
+        min_slot: usize,
+
+        /// Maximum slot (confirmation targets of the epochs up to the one of this slot, the epoch after it has to be synched)
+        #[arg(long)]
+        max_slot: usize,
+
+        /// Operator label file (CSV lines validator_index,operator, or JSON object from validator index to operator)
+        #[arg(long)]
+        operator_labels: String,
+
+        /// Confirmation quorum
+        #[arg(long, num_args = 1.., default_values_t = [0.67])]
+        quorum: Vec<f64>,
+    },
+
+    /// Report inclusion-delay distributions of the votes of every epoch, per target and per committee
+    InclusionDelay {
+        /// Caching database path
+        #[arg(long, default_value = "cache.rocksdb")]
+        db_path: String,
+
+        /// Minimum slot (votes of the epochs from the one of this slot on)
+        #[arg(long, default_value = "0")]
+        min_slot: usize,
+
+        /// Maximum slot (votes of the epochs up to the one of this slot, the epoch after it has to be synched)
+        #[arg(long)]
+        max_slot: usize,
+
+        /// Also report the distribution of every committee
+        #[arg(long)]
+        per_committee: bool,
+    },
+
+    /// Extract the part of caching database needed to run the confirmation rule on a slot range into a new database
+    Extract {
+        /// Caching database path
+        #[arg(long, default_value = "cache.rocksdb")]
+        db_path: String,
+
+        /// Minimum slot to extract
+        #[arg(long, default_value = "0")]
+        min_slot: usize,
+
+        /// Maximum slot to extract
+        #[arg(long)]
+        max_slot: usize,
+
+        /// Path of the extracted database (must not exist)
+        #[arg(long)]
+        out: String,
+    },
+
+    /// Check consistency of caching database and optionally mark inconsistent slots for synchronization again
+    CheckDb {
+        /// Caching database path
+        #[arg(long, default_value = "cache.rocksdb")]
+        db_path: String,
+
+        /// Minimum slot to check
+        #[arg(long, default_value = "0")]
+        min_slot: usize,
+
+        /// Maximum slot to check
+        #[arg(long)]
+        max_slot: usize,
+
+        /// Mark slots and epoch states with problems as not synched, so that sync fetches them again
+        #[arg(long)]
+        repair: bool,
     },
 
     /// Run flexible confirmation-rule based on consensus metadata found in caching database
@@ -53,17 +211,172 @@ enum Commands {
         #[arg(long, default_value = "cache.rocksdb")]
         db_path: String,
 
+        /// Confirmation rule to replay
+        #[arg(long, value_enum, default_value = "flexible-ffg")]
+        rule: crate::confrule::Rule,
+
+        /// What a quorum of FFG votes for an epoch boundary block confirms (flexible-ffg rule)
+        #[arg(long, value_enum, default_value = "finalized")]
+        ffg_target: crate::confrule::FfgTarget,
+
+        /// Validators the quorums are relative to (flexible-ffg rule), several are replayed side by side
+        #[arg(long, value_enum, num_args = 1.., default_value = "committee")]
+        quorum_base: Vec<crate::confrule::QuorumBase>,
+
+        /// Trailing window of epochs in which validators with an included vote count as active
+        #[arg(long, default_value_t = 2)]
+        active_window_epochs: usize,
+
+        /// Validator indices to exclude in a counterfactual replay (see --exclusion-mode), replayed side by side with the baseline
+        #[arg(long, num_args = 1.., value_delimiter = ',')]
+        exclude_validators: Vec<usize>,
+
+        /// Files of validator indices (one per line) to exclude in a counterfactual replay
+        #[arg(long, num_args = 1..)]
+        exclude_validators_file: Vec<String>,
+
+        /// Operator label file (CSV lines validator_index,operator)
+        #[arg(long)]
+        operator_labels: Option<String>,
+
+        /// Operators whose validators to exclude in a counterfactual replay (labeled in the operator label file)
+        #[arg(long, num_args = 1.., value_delimiter = ',', requires = "operator_labels")]
+        exclude_operators: Vec<String>,
+
+        /// How the excluded validators behave in the counterfactual replay
+        #[arg(long, value_enum, default_value = "offline")]
+        exclusion_mode: crate::confrule::ExclusionMode,
+
         /// Confirmation quorum
         #[arg(long, num_args = 1..)]
         quorum: Vec<f64>,
 
+        /// Range of confirmation quorums start:end:step (e.g., 0.67:0.99:0.01)
+        #[arg(long, num_args = 1..)]
+        quorum_range: Vec<crate::confrule::QuorumRange>,
+
         /// Minimum slot to process
         #[arg(long, default_value = "0")]
         min_slot: usize,
 
         /// Maximum slot to process
+        #[arg(long, required_unless_present = "window")]
+        max_slot: Option<usize>,
+
+        /// Slot windows min_slot:max_slot to process one after another, instead of minimum and maximum slot
+        #[arg(long, num_args = 1.., conflicts_with_all = ["min_slot", "max_slot", "checkpoint_db_path"])]
+        window: Vec<crate::confrule::SlotWindow>,
+
+        /// Write a table of confirmation latency statistics by window and quorum to this path (tab-separated)
         #[arg(long)]
-        max_slot: usize,
+        summary_path: Option<String>,
+
+        /// Replay epoch ranges in parallel on all CPU cores (output is identical to a sequential run)
+        #[arg(long, conflicts_with = "checkpoint_db_path")]
+        parallel: bool,
+
+        /// Number of threads for parallel replay (defaults to number of CPU cores)
+        #[arg(long, requires = "parallel")]
+        threads: Option<usize>,
+
+        /// Checkpoint database path, replay state is persisted there periodically and at the end
+        #[arg(long)]
+        checkpoint_db_path: Option<String>,
+
+        /// Interval between checkpoints in epochs
+        #[arg(long, default_value_t = 256)]
+        checkpoint_interval: usize,
+
+        /// Resume replay from the latest checkpoint, processing only slots after it
+        #[arg(long, requires = "checkpoint_db_path")]
+        resume: bool,
+
+        /// Report wall-clock confirmation latency of every block (LATENCY lines), taking as confirmation time the given reference point
+        #[arg(long, value_enum)]
+        latency: Option<crate::confrule::ConfirmationTime>,
+
+        /// Genesis time (unix time) used to convert slots to wall-clock time and to determine the current slot
+        #[arg(long, default_value_t = crate::utils::GENESIS_TIME)]
+        genesis_time: u64,
+
+        /// Address to serve Prometheus metrics on (disabled if not given)
+        #[arg(long)]
+        metrics_listen: Option<std::net::SocketAddr>,
+    },
+
+    /// Explain the confirmation of the target of an epoch: accumulation of its votes block by block, and when each quorum is crossed
+    Explain {
+        /// Caching database path
+        #[arg(long, default_value = "cache.rocksdb")]
+        db_path: String,
+
+        /// Epoch of the confirmation target
+        #[arg(long)]
+        epoch: usize,
+
+        /// Confirmation quorum
+        #[arg(long, num_args = 1.., required = true)]
+        quorum: Vec<f64>,
+    },
+
+    /// Serve confirmation status over HTTP, following the caching database as it is synchronized
+    Serve {
+        /// Caching database path
+        #[arg(long, default_value = "cache.rocksdb")]
+        db_path: String,
+
+        /// Path for the secondary instance of the caching database used to follow sync
+        #[arg(long, default_value = "cache.rocksdb.secondary")]
+        secondary_path: String,
+
+        /// Confirmation rule to replay
+        #[arg(long, value_enum, default_value = "flexible-ffg")]
+        rule: crate::confrule::Rule,
+
+        /// What a quorum of FFG votes for an epoch boundary block confirms (flexible-ffg rule)
+        #[arg(long, value_enum, default_value = "finalized")]
+        ffg_target: crate::confrule::FfgTarget,
+
+        /// Validators the quorums are relative to (flexible-ffg rule)
+        #[arg(long, value_enum, default_value = "committee")]
+        quorum_base: crate::confrule::QuorumBase,
+
+        /// Trailing window of epochs in which validators with an included vote count as active
+        #[arg(long, default_value_t = 2)]
+        active_window_epochs: usize,
+
+        /// Confirmation quorum
+        #[arg(long, num_args = 1..)]
+        quorum: Vec<f64>,
+
+        /// Minimum slot to process
+        #[arg(long, default_value = "0")]
+        min_slot: usize,
+
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: std::net::SocketAddr,
+
+        /// Interval between polls of the caching database for newly synchronized slots (in seconds)
+        #[arg(long, default_value_t = 12.0)]
+        poll_seconds: f64,
+
+        /// Number of slots that sync has to be past a slot before it is replayed
+        #[arg(long, default_value_t = 32)]
+        stability_slots: usize,
+
+        /// Address to listen on for execution JSON-RPC requests, which are forwarded to `--el-rpc-url`
+        /// with block tags `confirmed-<quorum>` resolved to the highest confirmed execution block
+        #[arg(long, requires = "el_rpc_url")]
+        el_proxy_listen: Option<std::net::SocketAddr>,
+
+        /// Execution node JSON-RPC endpoint that the execution JSON-RPC proxy forwards requests to
+        #[arg(long, requires = "el_proxy_listen")]
+        el_rpc_url: Option<String>,
+
+        /// Genesis time (unix time) used to convert slots to wall-clock time
+        #[arg(long, default_value_t = crate::utils::GENESIS_TIME)]
+        genesis_time: u64,
     },
 }
 
@@ -86,29 +399,197 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     match cli.command {
         Commands::Sync {
             db_path,
-            rpc_url,
+            rpc_urls,
+            client_options,
             min_slot,
             max_slot,
+            fill_gaps,
+            genesis_time,
+            committees,
+            committees_state_id,
             rl_requests,
             rl_seconds,
+            metrics_listen,
         } => {
+            crate::metrics::spawn_server(metrics_listen);
             crate::sync::main(
                 db_path,
-                rpc_url,
-                min_slot,
-                max_slot,
+                rpc_urls,
+                client_options,
+                crate::sync::SlotRangeOptions {
+                    min_slot,
+                    max_slot,
+                    fill_gaps,
+                    genesis_time,
+                },
                 Ratelimiter::builder(rl_requests as u64, Duration::from_secs_f64(rl_seconds))
                     .max_tokens(rl_requests as u64 * 3)
                     .build()
                     .unwrap(),
+                crate::sync::CommitteeOptions {
+                    source: committees,
+                    state_id: committees_state_id,
+                },
             )
             .await
         }
+        Commands::ImportEra { db_path, era_files } => crate::era::main(db_path, era_files).await,
+        Commands::Status { db_path } => crate::status::main(db_path).await,
+        Commands::Export {
+            db_path,
+            min_slot,
+            max_slot,
+            output_dir,
+            formats,
+        } => crate::export::main(db_path, min_slot, max_slot, output_dir, formats).await,
+        Commands::EntityVotes {
+            db_path,
+            min_slot,
+            max_slot,
+            operator_labels,
+            quorum,
+        } => crate::entities::main(db_path, min_slot, max_slot, operator_labels, quorum).await,
+        Commands::InclusionDelay {
+            db_path,
+            min_slot,
+            max_slot,
+            per_committee,
+        } => crate::inclusion::main(db_path, min_slot, max_slot, per_committee).await,
+        Commands::Extract {
+            db_path,
+            min_slot,
+            max_slot,
+            out,
+        } => crate::extract::main(db_path, min_slot, max_slot, out).await,
+        Commands::CheckDb {
+            db_path,
+            min_slot,
+            max_slot,
+            repair,
+        } => crate::checkdb::main(db_path, min_slot, max_slot, repair).await,
         Commands::ConfRule {
             db_path,
+            rule,
+            ffg_target,
+            quorum_base,
+            active_window_epochs,
+            exclude_validators,
+            exclude_validators_file,
+            operator_labels,
+            exclude_operators,
+            exclusion_mode,
             quorum,
+            quorum_range,
             min_slot,
             max_slot,
-        } => crate::confrule::main(db_path, quorum, min_slot, max_slot).await,
+            window,
+            summary_path,
+            parallel,
+            threads,
+            checkpoint_db_path,
+            checkpoint_interval,
+            resume,
+            latency,
+            genesis_time,
+            metrics_listen,
+        } => {
+            crate::metrics::spawn_server(metrics_listen);
+            let threads = if parallel {
+                Some(threads.unwrap_or_else(crate::utils::get_available_cpucores).max(1))
+            } else {
+                None
+            };
+            let mut quorums = quorum;
+            for q in quorum_range.into_iter().flat_map(|r| r.0) {
+                if !quorums.contains(&q) {
+                    quorums.push(q);
+                }
+            }
+            let excluded_validators = crate::labels::resolve_validators(
+                exclude_validators,
+                &exclude_validators_file,
+                operator_labels.as_deref(),
+                &exclude_operators,
+            )?;
+            let mut rule_options = vec![];
+            for quorum_base in quorum_base {
+                let baseline = crate::confrule::RuleOptions {
+                    rule,
+                    quorums: quorums.clone(),
+                    ffg_target,
+                    quorum_base,
+                    active_window_epochs,
+                    excluded_validators: std::collections::BTreeSet::new(),
+                    exclusion_mode,
+                };
+                rule_options.push(baseline.clone());
+                // counterfactual replay after the baseline it is compared with
+                if !excluded_validators.is_empty() {
+                    rule_options.push(crate::confrule::RuleOptions {
+                        excluded_validators: excluded_validators.clone(),
+                        ..baseline
+                    });
+                }
+            }
+            let windows = match max_slot {
+                Some(max_slot) => vec![crate::confrule::SlotWindow { min_slot, max_slot }],
+                None => window,
+            };
+            crate::confrule::main(
+                db_path,
+                rule_options,
+                windows,
+                threads,
+                checkpoint_db_path.map(|db_path| crate::confrule::CheckpointOptions {
+                    db_path,
+                    interval: checkpoint_interval.max(1),
+                    resume,
+                }),
+                crate::confrule::LatencyOptions {
+                    confirmation_time: latency,
+                    genesis_time,
+                },
+                summary_path,
+            )
+            .await
+        }
+        Commands::Explain { db_path, epoch, quorum } => crate::confrule::explain::main(db_path, epoch, quorum).await,
+        Commands::Serve {
+            db_path,
+            secondary_path,
+            rule,
+            ffg_target,
+            quorum_base,
+            active_window_epochs,
+            quorum,
+            min_slot,
+            listen,
+            poll_seconds,
+            stability_slots,
+            el_proxy_listen,
+            el_rpc_url,
+            genesis_time,
+        } => {
+            crate::service::main(
+                db_path,
+                secondary_path,
+                crate::confrule::RuleOptions {
+                    rule,
+                    quorums: quorum,
+                    ffg_target,
+                    quorum_base,
+                    active_window_epochs,
+                    excluded_validators: std::collections::BTreeSet::new(),
+                    exclusion_mode: crate::confrule::ExclusionMode::Offline,
+                },
+                listen,
+                crate::service::FollowOptions { min_slot, poll_seconds, stability_slots },
+                el_proxy_listen
+                    .zip(el_rpc_url)
+                    .map(|(listen, rpc_url)| crate::service::ExecutionProxyOptions { listen, rpc_url }),
+                genesis_time,
+            )
+            .await
+        }
     }
 }
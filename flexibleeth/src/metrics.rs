@@ -0,0 +1,94 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Response, Server};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_counter, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    Counter, Encoder, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+lazy_static! {
+    pub static ref SYNC_LAST_SYNCED_SLOT: IntGauge = register_int_gauge!(
+        "flexibleeth_sync_last_synced_slot",
+        "Last slot marked as synched"
+    )
+    .unwrap();
+    pub static ref RPC_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "flexibleeth_rpc_requests_total",
+        "Requests to the beacon chain RPC endpoints",
+        &["route", "endpoint"]
+    )
+    .unwrap();
+    pub static ref RPC_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "flexibleeth_rpc_errors_total",
+        "Failed requests to the beacon chain RPC endpoints",
+        &["route", "endpoint", "class"]
+    )
+    .unwrap();
+    pub static ref RPC_DISAGREEMENTS: IntCounterVec = register_int_counter_vec!(
+        "flexibleeth_rpc_disagreements_total",
+        "Cross-checked requests for which a beacon chain RPC endpoint disagreed with the first one",
+        &["route", "endpoint"]
+    )
+    .unwrap();
+    pub static ref RATELIMITER_WAIT_SECONDS: Counter = register_counter!(
+        "flexibleeth_ratelimiter_wait_seconds_total",
+        "Time spent waiting for the rate limiter"
+    )
+    .unwrap();
+    pub static ref CONFRULE_CONFIRMED_TIP_SLOT: IntGaugeVec = register_int_gauge_vec!(
+        "flexibleeth_confrule_confirmed_tip_slot",
+        "Confirmed tip slot",
+        &["quorum"]
+    )
+    .unwrap();
+    pub static ref CONFRULE_CONFIRMED_EXECUTION_BLOCK_NUMBER: IntGaugeVec = register_int_gauge_vec!(
+        "flexibleeth_confrule_confirmed_execution_block_number",
+        "Highest confirmed execution block number",
+        &["quorum"]
+    )
+    .unwrap();
+    pub static ref CONFRULE_PROCESSED_SLOT: IntGauge = register_int_gauge!(
+        "flexibleeth_confrule_processed_slot",
+        "Last slot processed by the confirmation rule"
+    )
+    .unwrap();
+    pub static ref CONFRULE_TARGET_VOTES: IntGaugeVec = register_int_gauge_vec!(
+        "flexibleeth_confrule_target_votes",
+        "Votes counted for the live confirmation targets",
+        &["epoch"]
+    )
+    .unwrap();
+}
+
+/// Serves the metrics of the default registry for scraping at `/metrics`
+pub async fn serve(listen: SocketAddr) -> Result<(), hyper::Error> {
+    let make_svc = make_service_fn(|_conn| async {
+        Ok::<_, Infallible>(service_fn(|_req| async {
+            let mut buffer = vec![];
+            TextEncoder::new()
+                .encode(&prometheus::gather(), &mut buffer)
+                .unwrap();
+            Ok::<_, Infallible>(
+                Response::builder()
+                    .header(hyper::header::CONTENT_TYPE, TextEncoder::new().format_type())
+                    .body(Body::from(buffer))
+                    .unwrap(),
+            )
+        }))
+    });
+    log::info!("Serving metrics on {}", listen);
+    Server::bind(&listen).serve(make_svc).await
+}
+
+/// Starts the metrics endpoint in the background if an address is given
+pub fn spawn_server(listen: Option<SocketAddr>) {
+    if let Some(listen) = listen {
+        tokio::spawn(async move {
+            if let Err(e) = serve(listen).await {
+                log::error!("Metrics endpoint failed: {}", e);
+            }
+        });
+    }
+}
@@ -0,0 +1,400 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use rocksdb::DB;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+
+use super::{respond, respond_error, ServiceState};
+use crate::confrule::replay;
+use crate::data;
+
+/// Prefix of the block tags resolved to the highest execution block confirmed at a quorum,
+/// e.g., `confirmed-0.95`
+const CONFIRMED_TAG_PREFIX: &str = "confirmed-";
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+/// Where to listen for execution JSON-RPC requests, and the execution node to forward them to
+pub struct ExecutionProxyOptions {
+    pub listen: SocketAddr,
+    pub rpc_url: String,
+}
+
+/// Serves execution JSON-RPC requests, forwarded to the execution node with the confirmed
+/// block tags among their params resolved to block numbers (or hashes)
+pub async fn serve(db: Arc<DB>, state: Arc<RwLock<ServiceState>>, options: ExecutionProxyOptions) -> Result<(), hyper::Error> {
+    log::info!("Serving execution JSON-RPC proxy for {} on {}", options.rpc_url, options.listen);
+    let client = reqwest::Client::new();
+    let rpc_url = Arc::new(options.rpc_url);
+    let make_svc = make_service_fn(move |_conn| {
+        let db = db.clone();
+        let state = state.clone();
+        let client = client.clone();
+        let rpc_url = rpc_url.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let db = db.clone();
+                let state = state.clone();
+                let client = client.clone();
+                let rpc_url = rpc_url.clone();
+                async move { Ok::<_, Infallible>(handle(&db, &state, &client, &rpc_url, req).await) }
+            }))
+        }
+    });
+    Server::bind(&options.listen).serve(make_svc).await
+}
+
+/// Methods whose first param is a log filter, with block parameters `fromBlock` and `toBlock`
+const LOG_FILTER_METHODS: [&str; 2] = ["eth_getLogs", "eth_newFilter"];
+
+/// Methods whose block parameter may be an EIP-1898 block object, so that a confirmed block is
+/// referred to by hash rather than by number (which a reorg could change)
+const EIP_1898_METHODS: [&str; 6] = ["eth_getBalance", "eth_getStorageAt", "eth_getTransactionCount", "eth_getCode", "eth_call", "eth_getProof"];
+
+/// Position of the block parameter among the params of a method, if it has one
+fn block_param_position(method: &str) -> Option<usize> {
+    match method {
+        "eth_getBlockByNumber"
+        | "eth_getBlockTransactionCountByNumber"
+        | "eth_getUncleCountByBlockNumber"
+        | "eth_getTransactionByBlockNumberAndIndex"
+        | "eth_getUncleByBlockNumberAndIndex"
+        | "eth_getBlockReceipts" => Some(0),
+        "eth_getBalance" | "eth_getCode" | "eth_getTransactionCount" | "eth_call" | "eth_estimateGas" | "eth_createAccessList" | "eth_feeHistory" => Some(1),
+        "eth_getStorageAt" | "eth_getProof" => Some(2),
+        _ => None,
+    }
+}
+
+/// Number of an execution block as JSON-RPC quantity
+fn block_number(execution_payload: &data::ExecutionPayload) -> Value {
+    Value::String(format!("{:#x}", execution_payload.block_number))
+}
+
+fn jsonrpc_error(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+/// Resolves confirmed block tags, every quorum once per HTTP request so that all tags of a batch
+/// refer to the same confirmed tips
+struct TagResolver<'a> {
+    db: &'a DB,
+    state: &'a RwLock<ServiceState>,
+    resolved: HashMap<String, data::ExecutionPayload>,  // by quorum
+}
+
+impl TagResolver<'_> {
+    /// Highest execution block confirmed at a quorum, or the JSON-RPC error code and message
+    fn resolve_quorum(&mut self, quorum: &str) -> Result<data::ExecutionPayload, (i64, String)> {
+        if let Some(execution_payload) = self.resolved.get(quorum) {
+            return Ok(execution_payload.clone());
+        }
+        let tip_slot = {
+            let state = self.state.read().unwrap();
+            let quorum_idx = quorum
+                .parse::<f64>()
+                .ok()
+                .and_then(|q| state.quorum_idx(q))
+                .ok_or_else(|| (INVALID_PARAMS, format!("Quorum {} is not tracked, tracked quorums are {:?}", quorum, state.quorums)))?;
+            if state.ledgers[quorum_idx].is_empty() {
+                return Err((SERVER_ERROR, format!("No block confirmed at quorum {} yet", quorum)));
+            }
+            state.confirmed_tip_slot(quorum_idx)
+        };
+        let execution_payload = match replay::get_confirmed_execution_payload(self.db, tip_slot) {
+            Ok(Some(execution_payload)) => execution_payload,
+            Ok(None) => return Err((SERVER_ERROR, format!("No execution block known for confirmed tip slot {}", tip_slot))),
+            Err(e) => return Err((SERVER_ERROR, e.to_string())),
+        };
+        log::debug!(
+            "Resolved block tag {}{} to block {} ({})",
+            CONFIRMED_TAG_PREFIX,
+            quorum,
+            execution_payload.block_number,
+            execution_payload.block_hash
+        );
+        self.resolved.insert(quorum.to_string(), execution_payload.clone());
+        Ok(execution_payload)
+    }
+
+    /// Confirmed execution block of a block tag, or `None` if the value is not a confirmed tag
+    fn resolve_tag(&mut self, tag: &str) -> Result<Option<data::ExecutionPayload>, (i64, String)> {
+        match tag.strip_prefix(CONFIRMED_TAG_PREFIX) {
+            Some(quorum) => self.resolve_quorum(quorum).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Replaces a confirmed block tag given as block parameter, also as `blockNumber` of an
+    /// EIP-1898 block object: with the block hash if the method accepts block objects, otherwise
+    /// with the block number
+    fn resolve_block_param(&mut self, param: &mut Value, accepts_block_hash: bool) -> Result<(), (i64, String)> {
+        let tag = match param {
+            Value::String(tag) => tag,
+            Value::Object(object) if accepts_block_hash => match object.get("blockNumber") {
+                Some(Value::String(tag)) => tag,
+                _ => return Ok(()),
+            },
+            _ => return Ok(()),
+        };
+        if let Some(execution_payload) = self.resolve_tag(tag)? {
+            *param = match accepts_block_hash {
+                true => json!({ "blockHash": execution_payload.block_hash }),
+                false => block_number(&execution_payload),
+            };
+        }
+        Ok(())
+    }
+
+    /// Replaces the confirmed block tags in the params of a request: at the block parameter of
+    /// the method, or in `fromBlock`/`toBlock` of a log filter. Other params are forwarded as is.
+    fn resolve_tags(&mut self, method: &str, params: &mut Value) -> Result<(), (i64, String)> {
+        if let Some(position) = block_param_position(method) {
+            if let Some(param) = params.get_mut(position) {
+                self.resolve_block_param(param, EIP_1898_METHODS.contains(&method))?;
+            }
+        } else if LOG_FILTER_METHODS.contains(&method) {
+            if let Some(Value::Object(filter)) = params.get_mut(0) {
+                for key in ["fromBlock", "toBlock"] {
+                    if let Some(Value::String(tag)) = filter.get(key) {
+                        if let Some(execution_payload) = self.resolve_tag(tag)? {
+                            filter.insert(key.to_string(), block_number(&execution_payload));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves the tags in the params of a request, or returns the error response to the request
+    fn resolve_request(&mut self, request: &mut Value) -> Result<(), Value> {
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        match request.as_object_mut() {
+            Some(request) => {
+                let method = request.get("method").and_then(Value::as_str).unwrap_or_default().to_string();
+                match request.get_mut("params") {
+                    Some(params) => self.resolve_tags(&method, params).map_err(|(code, message)| jsonrpc_error(id, code, &message)),
+                    None => Ok(()),
+                }
+            }
+            None => Err(jsonrpc_error(id, INVALID_REQUEST, "Invalid request")),
+        }
+    }
+}
+
+async fn forward(client: &reqwest::Client, rpc_url: &str, body: &Value) -> Result<(StatusCode, Vec<u8>), reqwest::Error> {
+    let response = client
+        .post(rpc_url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .send()
+        .await?;
+    let status = StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    Ok((status, response.bytes().await?.to_vec()))
+}
+
+fn relay(status: StatusCode, body: Vec<u8>) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+async fn handle(db: &DB, state: &RwLock<ServiceState>, client: &reqwest::Client, rpc_url: &str, req: Request<Body>) -> Response<Body> {
+    if req.method() != Method::POST {
+        return respond_error(StatusCode::METHOD_NOT_ALLOWED, "Only POST is supported");
+    }
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return respond_error(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+    let mut request = match serde_json::from_slice::<Value>(&body) {
+        Ok(request) => request,
+        Err(e) => return respond(StatusCode::OK, jsonrpc_error(Value::Null, PARSE_ERROR, &e.to_string())),
+    };
+
+    let mut resolver = TagResolver { db, state, resolved: HashMap::new() };
+    // requests of a batch whose tags cannot be resolved are answered by the proxy, the others
+    // are forwarded
+    let errors = match &mut request {
+        Value::Array(requests) if requests.is_empty() => {
+            return respond(StatusCode::OK, jsonrpc_error(Value::Null, INVALID_REQUEST, "Empty batch"));
+        }
+        Value::Array(requests) => {
+            let mut errors = vec![];
+            requests.retain_mut(|request| match resolver.resolve_request(request) {
+                Ok(()) => true,
+                Err(error) => {
+                    errors.push(error);
+                    false
+                }
+            });
+            if requests.is_empty() {
+                return respond(StatusCode::OK, Value::Array(errors));
+            }
+            errors
+        }
+        request => {
+            if let Err(error) = resolver.resolve_request(request) {
+                return respond(StatusCode::OK, error);
+            }
+            vec![]
+        }
+    };
+
+    let (status, response) = match forward(client, rpc_url, &request).await {
+        Ok(response) => response,
+        Err(e) => {
+            log::warn!("Execution JSON-RPC request to {} failed: {}", rpc_url, e);
+            let id = request.get("id").cloned().unwrap_or(Value::Null);
+            return respond(StatusCode::BAD_GATEWAY, jsonrpc_error(id, SERVER_ERROR, &e.to_string()));
+        }
+    };
+    if errors.is_empty() {
+        return relay(status, response);
+    }
+    match serde_json::from_slice::<Vec<Value>>(&response) {
+        Ok(mut responses) => {
+            responses.extend(errors);
+            respond(status, Value::Array(responses))
+        }
+        Err(_) => relay(status, response),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    /// Starts a mock execution node answering every request with its method and params
+    fn mock_execution_node() -> String {
+        let echo = |request: &Value| json!({ "jsonrpc": "2.0", "id": request["id"], "result": { "method": request["method"], "params": request["params"] } });
+        let make_svc = make_service_fn(move |_conn| async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| async move {
+                let body = hyper::body::to_bytes(req.into_body()).await?;
+                let response = match serde_json::from_slice::<Value>(&body).unwrap() {
+                    Value::Array(requests) => Value::Array(requests.iter().map(echo).collect()),
+                    request => echo(&request),
+                };
+                Ok::<_, hyper::Error>(respond(StatusCode::OK, response))
+            }))
+        });
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_svc);
+        let rpc_url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        rpc_url
+    }
+
+    /// Proxy of a chain whose tip at slot 45 is confirmed at quorum 0.5, and nothing at quorum 0.8
+    struct Proxy {
+        db: testutil::TempDb,
+        state: RwLock<ServiceState>,
+        rpc_url: String,
+    }
+
+    impl Proxy {
+        fn new(name: &str) -> Self {
+            let db = testutil::TempDb::new(name);
+            testutil::write_chain(&db, 64, 4);
+            let state = RwLock::new(ServiceState { quorums: vec![0.5, 0.8], next_slot: 51, ledgers: vec![vec![(50, 45)], vec![]], genesis_time: crate::utils::GENESIS_TIME });
+            Self { db, state, rpc_url: mock_execution_node() }
+        }
+
+        fn confirmed(&self) -> data::ExecutionPayload {
+            replay::get_confirmed_execution_payload(self.db.db(), 45).unwrap().unwrap()
+        }
+
+        async fn request(&self, body: Value) -> Value {
+            let req = Request::post("/").body(Body::from(body.to_string())).unwrap();
+            let response = handle(self.db.db(), &self.state, &reqwest::Client::new(), &self.rpc_url, req).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap()).unwrap()
+        }
+    }
+
+    fn request(id: usize, method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+    }
+
+    fn response(responses: &Value, id: usize) -> &Value {
+        responses.as_array().unwrap().iter().find(|response| response["id"] == id).unwrap()
+    }
+
+    #[tokio::test]
+    async fn single_request_is_forwarded_with_resolved_tag() {
+        let proxy = Proxy::new("execution-proxy-single");
+        let number = format!("{:#x}", proxy.confirmed().block_number);
+        let response = proxy.request(request(1, "eth_getBlockByNumber", json!(["confirmed-0.5", false]))).await;
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"]["params"], json!([number, false]));
+    }
+
+    #[tokio::test]
+    async fn batch_resolves_tags_only_at_block_parameters() {
+        let proxy = Proxy::new("execution-proxy-batch");
+        let confirmed = proxy.confirmed();
+        let number = format!("{:#x}", confirmed.block_number);
+        let by_hash = json!({ "blockHash": confirmed.block_hash });
+        let call = json!({ "to": "0x01", "data": "confirmed-0.5" });
+        let responses = proxy
+            .request(json!([
+                request(1, "eth_getBlockByNumber", json!(["confirmed-0.5", true])),
+                request(2, "eth_call", json!([call, { "blockNumber": "confirmed-0.5" }])),
+                request(3, "eth_getBalance", json!(["0x01", "confirmed-0.5"])),
+                request(4, "eth_feeHistory", json!(["0x4", "confirmed-0.5", []])),
+                request(5, "eth_getLogs", json!([{ "fromBlock": "confirmed-0.5", "toBlock": "latest", "topics": ["confirmed-0.5"] }])),
+                request(6, "eth_sendRawTransaction", json!(["confirmed-0.5"])),
+            ]))
+            .await;
+        assert_eq!(responses.as_array().unwrap().len(), 6);
+        assert_eq!(response(&responses, 1)["result"]["params"], json!([number, true]));
+        assert_eq!(response(&responses, 2)["result"]["params"], json!([call, by_hash]));
+        assert_eq!(response(&responses, 3)["result"]["params"], json!(["0x01", by_hash]));
+        assert_eq!(response(&responses, 4)["result"]["params"], json!(["0x4", number, []]));
+        assert_eq!(
+            response(&responses, 5)["result"]["params"],
+            json!([{ "fromBlock": number, "toBlock": "latest", "topics": ["confirmed-0.5"] }])
+        );
+        assert_eq!(response(&responses, 6)["result"]["params"], json!(["confirmed-0.5"]));
+    }
+
+    #[tokio::test]
+    async fn batch_answers_unresolvable_tags_and_forwards_the_others() {
+        let proxy = Proxy::new("execution-proxy-mixed");
+        let number = format!("{:#x}", proxy.confirmed().block_number);
+        let responses = proxy
+            .request(json!([
+                request(1, "eth_getBlockByNumber", json!(["confirmed-0.9", false])),
+                request(2, "eth_getBlockByNumber", json!(["confirmed-0.5", false])),
+                request(3, "eth_getBlockByNumber", json!(["confirmed-0.8", false])),
+            ]))
+            .await;
+        assert_eq!(responses.as_array().unwrap().len(), 3);
+        assert_eq!(response(&responses, 1)["error"]["code"], INVALID_PARAMS);
+        assert_eq!(response(&responses, 2)["result"]["params"], json!([number, false]));
+        assert_eq!(response(&responses, 3)["error"]["code"], SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn unresolvable_tags_are_not_forwarded() {
+        let proxy = Proxy::new("execution-proxy-errors");
+        // quorum that is not tracked
+        let response = proxy.request(request(7, "eth_getBalance", json!(["0x01", "confirmed-0.9"]))).await;
+        assert_eq!((&response["id"], &response["error"]["code"]), (&json!(7), &json!(INVALID_PARAMS)));
+        assert!(response.get("result").is_none());
+        // no block confirmed at the quorum yet
+        let response = proxy.request(request(8, "eth_getBlockByNumber", json!(["confirmed-0.8", false]))).await;
+        assert_eq!((&response["id"], &response["error"]["code"]), (&json!(8), &json!(SERVER_ERROR)));
+        assert!(response.get("result").is_none());
+    }
+}
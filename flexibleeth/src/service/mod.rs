@@ -0,0 +1,409 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use rocksdb::{DB, Options};
+use serde_json::json;
+use std::collections::hash_map::DefaultHasher;
+use std::convert::Infallible;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+mod execution_proxy;
+pub use execution_proxy::ExecutionProxyOptions;
+use crate::confrule::{replay, RuleOptions};
+use crate::data;
+use crate::utils;
+
+/// Confirmation ledgers as replayed so far, shared between the follower and the HTTP handlers
+struct ServiceState {
+    quorums: Vec<f64>,
+    next_slot: usize,
+    ledgers: Vec<Vec<(usize, usize)>>,  // one per quorum: slot, confirmed tip slot
+    genesis_time: u64,
+}
+
+impl ServiceState {
+    fn quorum_idx(&self, quorum: f64) -> Option<usize> {
+        self.quorums.iter().position(|q| *q == quorum)
+    }
+
+    fn confirmed_tip_slot(&self, quorum_idx: usize) -> usize {
+        self.ledgers[quorum_idx].last().map_or(0, |(_, tip)| *tip)
+    }
+
+    /// Slot at which the given slot was first covered by the confirmed tip
+    fn confirmed_since_slot(&self, quorum_idx: usize, slot: usize) -> Option<usize> {
+        let ledger = &self.ledgers[quorum_idx];
+        let i = ledger.partition_point(|(_, tip)| *tip < slot);
+        ledger.get(i).map(|(t, _)| *t)
+    }
+}
+
+/// How the service follows the caching database
+pub struct FollowOptions {
+    pub min_slot: usize,
+    pub poll_seconds: f64,
+    pub stability_slots: usize, // slots are replayed once sync is this many slots past them
+}
+
+/// Replays the confirmation rule on the slots of the caching database as they are synched,
+/// and again from the first replayed slot that sync rewrites later (e.g., after `check-db --repair`)
+struct Follower<'a> {
+    db: &'a DB,
+    rule_options: RuleOptions,
+    min_slot: usize,
+    stability_slots: usize,
+    replay: replay::Replay<'a>,
+    fingerprints: Vec<u64>, // of the replayed slots, from the minimum slot on
+}
+
+impl<'a> Follower<'a> {
+    fn new(db: &'a DB, rule_options: RuleOptions, min_slot: usize, stability_slots: usize) -> Self {
+        Self {
+            db,
+            replay: replay::Replay::new(db, rule_options.clone(), min_slot),
+            rule_options,
+            min_slot,
+            stability_slots,
+            fingerprints: vec![],
+        }
+    }
+
+    fn is_synched(&self, slot: usize) -> Result<bool, replay::ReplayError> {
+        Ok(self.db.get(format!("slot_{}_synched", slot))?.is_some())
+    }
+
+    /// Fingerprint of whether a slot is synched and of its canonical block root
+    fn fingerprint(&self, slot: usize) -> Result<u64, replay::ReplayError> {
+        let mut hasher = DefaultHasher::new();
+        self.db.get(format!("slot_{}_synched", slot))?.hash(&mut hasher);
+        self.db.get(format!("block_{}", slot))?.hash(&mut hasher);
+        Ok(hasher.finish())
+    }
+
+    fn first_rewritten_slot(&self) -> Result<Option<usize>, replay::ReplayError> {
+        for (i, fingerprint) in self.fingerprints.iter().enumerate() {
+            if self.fingerprint(self.min_slot + i)? != *fingerprint {
+                return Ok(Some(self.min_slot + i));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Replays the slots synched since the last poll, after replaying again from the first
+    /// rewritten slot (warming up on the epochs before it, as a parallel chunk does)
+    fn poll(&mut self, state: &RwLock<ServiceState>) -> Result<(), replay::ReplayError> {
+        if let Some(rewritten_slot) = self.first_rewritten_slot()? {
+            log::warn!("Slot {} changed since it was replayed, replaying again from it", rewritten_slot);
+            let warmup_min_slot = std::cmp::max(
+                self.min_slot,
+                utils::epoch_to_slot(utils::slot_to_epoch(rewritten_slot).saturating_sub(replay::warmup_epochs(&self.rule_options))),
+            );
+            self.replay = replay::Replay::new(self.db, self.rule_options.clone(), warmup_min_slot);
+            for slot in warmup_min_slot..rewritten_slot {
+                self.replay.process_slot(slot)?;
+            }
+            self.fingerprints.truncate(rewritten_slot - self.min_slot);
+            let mut state = state.write().unwrap();
+            for ledger in state.ledgers.iter_mut() {
+                ledger.retain(|(slot, _)| *slot < rewritten_slot);
+            }
+            state.next_slot = rewritten_slot;
+        }
+
+        let mut slot = self.replay.get_state().next_slot;
+        while self.is_synched(slot)? && self.is_synched(slot + self.stability_slots)? {
+            // fingerprint before replaying, so that a concurrent rewrite is detected at the next poll
+            self.fingerprints.push(self.fingerprint(slot)?);
+            let entries = self.replay.process_slot(slot)?;
+            let mut state = state.write().unwrap();
+            for entry in entries {
+                // after replaying again, the replay does not know the tips confirmed before its warmup
+                if entry.tip > state.confirmed_tip_slot(entry.quorum_idx) {
+                    state.ledgers[entry.quorum_idx].push((entry.slot, entry.tip));
+                }
+            }
+            state.next_slot = slot + 1;
+            slot += 1;
+        }
+        log::debug!("Replayed up to slot {}", slot);
+        Ok(())
+    }
+}
+
+pub async fn main(
+    db_path: String,
+    secondary_path: String,
+    rule_options: RuleOptions,
+    listen: SocketAddr,
+    follow_options: FollowOptions,
+    execution_proxy_options: Option<ExecutionProxyOptions>,
+    genesis_time: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let FollowOptions { min_slot, poll_seconds, stability_slots } = follow_options;
+    let mut db_opts = Options::default();
+    db_opts.increase_parallelism(utils::get_available_cpucores() as i32);
+    db_opts.optimize_for_point_lookup(utils::get_available_ram() as u64 / 4);
+    // open as secondary instance to follow a concurrently running sync
+    let db = Arc::new(DB::open_as_secondary(&db_opts, &db_path, &secondary_path)?);
+
+    let state = Arc::new(RwLock::new(ServiceState {
+        ledgers: vec![vec![]; rule_options.quorums.len()],
+        quorums: rule_options.quorums.clone(),
+        next_slot: min_slot,
+        genesis_time,
+    }));
+
+    // follow the caching database and replay the confirmation rule on newly synched slots
+    let follower = {
+        let db = db.clone();
+        let state = state.clone();
+        tokio::task::spawn_blocking(move || -> Result<(), replay::ReplayError> {
+            let mut follower = Follower::new(&db, rule_options, min_slot, stability_slots);
+            loop {
+                db.try_catch_up_with_primary()?;
+                follower.poll(&state)?;
+                std::thread::sleep(Duration::from_secs_f64(poll_seconds));
+            }
+        })
+    };
+
+    let execution_proxy = {
+        let db = db.clone();
+        let state = state.clone();
+        async move {
+            match execution_proxy_options {
+                Some(options) => execution_proxy::serve(db, state, options).await,
+                None => std::future::pending().await,
+            }
+        }
+    };
+
+    let make_svc = make_service_fn(move |_conn| {
+        let db = db.clone();
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let response = handle(&db, &state, req);
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+    log::info!("Serving confirmation status on {}", listen);
+    let server = Server::bind(&listen).serve(make_svc);
+
+    tokio::select! {
+        res = server => res?,
+        res = execution_proxy => res?,
+        res = follower => res?.map_err(|e| e as Box<dyn std::error::Error>)?,
+    }
+
+    Ok(())
+}
+
+fn respond(status: StatusCode, body: serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn respond_error(status: StatusCode, message: &str) -> Response<Body> {
+    respond(status, json!({ "code": status.as_u16(), "message": message }))
+}
+
+fn query_param<'a>(req: &'a Request<Body>, name: &str) -> Option<&'a str> {
+    req.uri().query()?.split('&').find_map(|kv| match kv.split_once('=') {
+        Some((k, v)) if k == name => Some(v),
+        _ => None,
+    })
+}
+
+fn handle(db: &DB, state: &RwLock<ServiceState>, req: Request<Body>) -> Response<Body> {
+    if req.method() != Method::GET {
+        return respond_error(StatusCode::METHOD_NOT_ALLOWED, "Only GET is supported");
+    }
+    let state = state.read().unwrap();
+    let path: Vec<&str> = req.uri().path().trim_matches('/').split('/').collect();
+
+    if path == ["quorums"] {
+        return respond(StatusCode::OK, json!({ "quorums": state.quorums }));
+    }
+
+    let quorum_idx = match query_param(&req, "quorum").map(|q| q.parse::<f64>()) {
+        Some(Ok(q)) => match state.quorum_idx(q) {
+            Some(idx) => idx,
+            None => {
+                return respond_error(StatusCode::BAD_REQUEST, &format!("Quorum {} is not tracked, tracked quorums are {:?}", q, state.quorums));
+            }
+        },
+        _ => {
+            return respond_error(StatusCode::BAD_REQUEST, "Missing or invalid quorum parameter");
+        }
+    };
+    let quorum = state.quorums[quorum_idx];
+
+    match path[..] {
+        // GET /tip?quorum=<q>: confirmed tip at the given quorum
+        ["tip"] => {
+            let tip_slot = state.confirmed_tip_slot(quorum_idx);
+            let tip_root = match lookup_canonical_root(db, tip_slot) {
+                Ok(root) => root,
+                Err(e) => return respond_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+            };
+            respond(
+                StatusCode::OK,
+                json!({
+                    "quorum": quorum,
+                    "processed_slot": state.next_slot.saturating_sub(1),
+                    "confirmed_tip_slot": tip_slot,
+                    "confirmed_tip_root": tip_root,
+                }),
+            )
+        }
+        // GET /block/<root>?quorum=<q>: whether the block is confirmed at the given quorum, and since when
+        ["block", root] => {
+            if !root.starts_with("0x") {
+                return respond_error(StatusCode::BAD_REQUEST, "Invalid block root");
+            }
+            let blk = match db.get(format!("block_{}", root)) {
+                Ok(Some(serialized_blk)) => match bincode::deserialize::<data::Block>(&serialized_blk) {
+                    Ok(blk) => blk,
+                    Err(e) => return respond_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+                },
+                Ok(None) => return respond_error(StatusCode::NOT_FOUND, "Block not found"),
+                Err(e) => return respond_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+            };
+            let canonical = match lookup_canonical_root(db, blk.slot) {
+                Ok(canonical_root) => canonical_root.as_deref() == Some(root),
+                Err(e) => return respond_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+            };
+            let confirmed_since_slot = if canonical {
+                state.confirmed_since_slot(quorum_idx, blk.slot)
+            } else {
+                None
+            };
+            respond(
+                StatusCode::OK,
+                json!({
+                    "quorum": quorum,
+                    "root": root,
+                    "slot": blk.slot,
+                    "canonical": canonical,
+                    "confirmed": confirmed_since_slot.is_some(),
+                    "confirmed_since_slot": confirmed_since_slot,
+                    "confirmed_since_unixtime": confirmed_since_slot.map(|slot| utils::slot_to_unixtime(slot, state.genesis_time)),
+                }),
+            )
+        }
+        _ => respond_error(StatusCode::NOT_FOUND, "Unknown endpoint"),
+    }
+}
+
+fn lookup_canonical_root(db: &DB, slot: usize) -> Result<Option<data::Root>, Box<dyn std::error::Error>> {
+    match db.get(format!("block_{}", slot))? {
+        Some(serialized_blkroot) => Ok(Some(bincode::deserialize::<data::Root>(&serialized_blkroot)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    fn new_state(rule_options: &RuleOptions, min_slot: usize) -> RwLock<ServiceState> {
+        RwLock::new(ServiceState {
+            ledgers: vec![vec![]; rule_options.quorums.len()],
+            quorums: rule_options.quorums.clone(),
+            next_slot: min_slot,
+            genesis_time: utils::GENESIS_TIME,
+        })
+    }
+
+    fn replayed_ledgers(db: &DB, rule_options: &RuleOptions, min_slot: usize, max_slot: usize) -> Vec<Vec<(usize, usize)>> {
+        let mut replay = replay::Replay::new(db, rule_options.clone(), min_slot);
+        let mut ledgers = vec![vec![]; rule_options.quorums.len()];
+        for slot in min_slot..=max_slot {
+            for entry in replay.process_slot(slot).unwrap() {
+                ledgers[entry.quorum_idx].push((entry.slot, entry.tip));
+            }
+        }
+        ledgers
+    }
+
+    #[test]
+    fn follower_keeps_stability_margin() {
+        let db = testutil::TempDb::new("service-follower-margin");
+        let num_slots = 32 * 8;
+        testutil::write_chain(&db, num_slots, 2);
+        let (rule_options, min_slot) = (testutil::rule_options(&[0.5, 0.8]), 40);
+        let state = new_state(&rule_options, min_slot);
+        let mut follower = Follower::new(db.db(), rule_options.clone(), min_slot, 10);
+        follower.poll(&state).unwrap();
+        let state = state.read().unwrap();
+        assert_eq!(state.next_slot, num_slots - 10);
+        assert_eq!(state.ledgers, replayed_ledgers(db.db(), &rule_options, min_slot, num_slots - 11));
+    }
+
+    #[test]
+    fn follower_replays_rewritten_slots_again() {
+        let db = testutil::TempDb::new("service-follower-rewrite");
+        let num_slots = 32 * 12;
+        testutil::write_chain(&db, num_slots, 3);
+        let (rule_options, min_slot) = (testutil::rule_options(&[0.5, 0.8]), 40);
+        let state = new_state(&rule_options, min_slot);
+        let mut follower = Follower::new(db.db(), rule_options.clone(), min_slot, 0);
+        follower.poll(&state).unwrap();
+        let before = state.read().unwrap().ledgers.clone();
+        assert_eq!(before, replayed_ledgers(db.db(), &rule_options, min_slot, num_slots - 1));
+
+        // repair drops the slots, the follower polls while they are unsynched, and sync
+        // writes them again with blocks that include no votes
+        let rewritten = 200..232;
+        for slot in rewritten.clone() {
+            db.db().delete(format!("slot_{}_synched", slot)).unwrap();
+        }
+        follower.poll(&state).unwrap();
+        assert_eq!(state.read().unwrap().next_slot, rewritten.start);
+        assert!(state.read().unwrap().ledgers.iter().flatten().all(|(slot, _)| *slot < rewritten.start));
+        for slot in rewritten {
+            if let Some(blkroot) = lookup_canonical_root(db.db(), slot).unwrap() {
+                let key = format!("block_{}", blkroot);
+                let mut blk: data::Block = bincode::deserialize(&db.db().get(&key).unwrap().unwrap()).unwrap();
+                blk.body.attestations.clear();
+                db.put(&key, &blk);
+            }
+            db.put(&format!("slot_{}_synched", slot), &true);
+        }
+        follower.poll(&state).unwrap();
+
+        let after = replayed_ledgers(db.db(), &rule_options, min_slot, num_slots - 1);
+        assert_ne!(after, before);
+        let state = state.read().unwrap();
+        assert_eq!(state.next_slot, num_slots);
+        assert_eq!(state.ledgers, after);
+    }
+
+    #[tokio::test]
+    async fn block_reports_since_when_it_is_confirmed() {
+        let db = testutil::TempDb::new("service-block");
+        let num_slots = 32 * 8;
+        testutil::write_chain(&db, num_slots, 4);
+        let (rule_options, min_slot) = (testutil::rule_options(&[0.5]), 40);
+        let state = new_state(&rule_options, min_slot);
+        Follower::new(db.db(), rule_options, min_slot, 0).poll(&state).unwrap();
+        let (since_slot, tip) = state.read().unwrap().ledgers[0][0];
+        let root = lookup_canonical_root(db.db(), tip).unwrap().unwrap();
+
+        let req = Request::get(format!("/block/{}?quorum=0.5", root)).body(Body::empty()).unwrap();
+        let response = handle(db.db(), &state, req);
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+        assert_eq!(body["confirmed"], true);
+        assert_eq!(body["confirmed_since_slot"], since_slot);
+        assert_eq!(body["confirmed_since_unixtime"], utils::GENESIS_TIME + 12 * since_slot as u64);
+    }
+}
@@ -0,0 +1,229 @@
+use sha2::{Digest, Sha256};
+
+use crate::data;
+use crate::utils;
+
+pub const SHUFFLE_ROUND_COUNT: u8 = 90;
+pub const MAX_COMMITTEES_PER_SLOT: usize = 64;
+pub const TARGET_COMMITTEE_SIZE: usize = 128;
+pub const EPOCHS_PER_HISTORICAL_VECTOR: usize = 65536;
+pub const MIN_SEED_LOOKAHEAD: usize = 1;
+pub const DOMAIN_BEACON_ATTESTER: [u8; 4] = [0x01, 0x00, 0x00, 0x00];
+
+pub type Bytes32 = [u8; 32];
+
+pub fn bytes32_from_0xhex(bytes: &str) -> Result<Bytes32, Box<dyn std::error::Error>> {
+    let bytes = hex::decode(bytes.trim_start_matches("0x"))?;
+    bytes
+        .try_into()
+        .map_err(|_| "Expected 32 bytes".into())
+}
+
+/// Index into the RANDAO mixes of the mix that seeds the shuffling of `epoch`
+pub fn seed_randao_mix_index(epoch: usize) -> usize {
+    (epoch + EPOCHS_PER_HISTORICAL_VECTOR - MIN_SEED_LOOKAHEAD - 1) % EPOCHS_PER_HISTORICAL_VECTOR
+}
+
+/// Attester shuffling seed of `epoch` (spec: `get_seed` with `DOMAIN_BEACON_ATTESTER`), where
+/// `randao_mix` is the mix at `seed_randao_mix_index(epoch)`
+pub fn get_attester_seed(epoch: usize, randao_mix: &Bytes32) -> Bytes32 {
+    let mut hasher = Sha256::new();
+    hasher.update(DOMAIN_BEACON_ATTESTER);
+    hasher.update((epoch as u64).to_le_bytes());
+    hasher.update(randao_mix);
+    hasher.finalize().into()
+}
+
+/// Applies `compute_shuffled_index` to every index at once: entry `i` of the result is the
+/// index that position `i` of the shuffled list takes from the unshuffled list
+pub fn compute_shuffled_indices(index_count: usize, seed: &Bytes32) -> Vec<usize> {
+    let mut indices: Vec<usize> = (0..index_count).collect();
+    if index_count == 0 {
+        return indices;
+    }
+    for current_round in 0..SHUFFLE_ROUND_COUNT {
+        let pivot_hash: Bytes32 = Sha256::new()
+            .chain_update(seed)
+            .chain_update([current_round])
+            .finalize()
+            .into();
+        let pivot = (u64::from_le_bytes(pivot_hash[0..8].try_into().unwrap()) % index_count as u64) as usize;
+
+        // source bits of all positions in this round, 256 positions per hash
+        let sources: Vec<Bytes32> = (0..=(index_count - 1) / 256)
+            .map(|position_chunk| {
+                Sha256::new()
+                    .chain_update(seed)
+                    .chain_update([current_round])
+                    .chain_update((position_chunk as u32).to_le_bytes())
+                    .finalize()
+                    .into()
+            })
+            .collect();
+
+        for index in indices.iter_mut() {
+            let flip = (pivot + index_count - *index) % index_count;
+            let position = std::cmp::max(*index, flip);
+            let byte = sources[position / 256][(position % 256) / 8];
+            if (byte >> (position % 8)) & 1 == 1 {
+                *index = flip;
+            }
+        }
+    }
+    indices
+}
+
+/// Ascending indices of the validators active in `epoch`, given the activation and exit epochs
+/// of the validator registry. The registry of any later state can be used, since it only
+/// grows and exits are never backdated.
+pub fn get_active_validator_indices(validator_epochs: &[(usize, usize)], epoch: usize) -> Vec<usize> {
+    validator_epochs
+        .iter()
+        .enumerate()
+        .filter(|(_, (activation_epoch, exit_epoch))| *activation_epoch <= epoch && epoch < *exit_epoch)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+pub fn get_committee_count_per_slot(active_validator_count: usize) -> usize {
+    (active_validator_count / utils::SLOTS_PER_EPOCH / TARGET_COMMITTEE_SIZE).clamp(1, MAX_COMMITTEES_PER_SLOT)
+}
+
+/// Beacon committees of all slots of `epoch` (spec: `get_beacon_committee`), in the order of
+/// the committees endpoint of the beacon chain RPC, given the ascending indices of the
+/// validators active in the epoch and the attester shuffling seed of the epoch
+pub fn get_beacon_committees(epoch: usize, active_validator_indices: &[usize], seed: &Bytes32) -> Vec<data::CommitteeAssignment> {
+    let n = active_validator_indices.len();
+    let shuffled_indices = compute_shuffled_indices(n, seed);
+    let committees_per_slot = get_committee_count_per_slot(n);
+    let count = committees_per_slot * utils::SLOTS_PER_EPOCH;
+
+    let mut committees = Vec::with_capacity(count);
+    for slot_offset in 0..utils::SLOTS_PER_EPOCH {
+        for index in 0..committees_per_slot {
+            let committee_idx = slot_offset * committees_per_slot + index;
+            let start = n * committee_idx / count;
+            let end = n * (committee_idx + 1) / count;
+            committees.push(data::CommitteeAssignment {
+                index,
+                slot: utils::epoch_to_slot(epoch) + slot_offset,
+                validators: shuffled_indices[start..end]
+                    .iter()
+                    .map(|i| active_validator_indices[*i])
+                    .collect(),
+            });
+        }
+    }
+    committees
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `compute_shuffled_index` of the consensus specs, one index at a time
+    fn spec_compute_shuffled_index(mut index: usize, index_count: usize, seed: &Bytes32) -> usize {
+        for current_round in 0..SHUFFLE_ROUND_COUNT {
+            let pivot_hash = Sha256::new().chain_update(seed).chain_update([current_round]).finalize();
+            let pivot = (u64::from_le_bytes(pivot_hash[0..8].try_into().unwrap()) % index_count as u64) as usize;
+            let flip = (pivot + index_count - index) % index_count;
+            let position = std::cmp::max(index, flip);
+            let source = Sha256::new()
+                .chain_update(seed)
+                .chain_update([current_round])
+                .chain_update(((position / 256) as u32).to_le_bytes())
+                .finalize();
+            let byte = source[(position % 256) / 8];
+            if (byte >> (position % 8)) % 2 == 1 {
+                index = flip;
+            }
+        }
+        index
+    }
+
+    fn seed(tag: u8) -> Bytes32 {
+        Sha256::digest([tag]).into()
+    }
+
+    #[test]
+    fn shuffled_indices_match_spec() {
+        for (index_count, tag) in [(1, 0), (2, 1), (3, 2), (100, 3), (256, 4), (257, 5), (1000, 6)] {
+            let seed = seed(tag);
+            let expected: Vec<usize> = (0..index_count)
+                .map(|index| spec_compute_shuffled_index(index, index_count, &seed))
+                .collect();
+            assert_eq!(compute_shuffled_indices(index_count, &seed), expected, "{} indices", index_count);
+        }
+        assert!(compute_shuffled_indices(0, &seed(7)).is_empty());
+    }
+
+    #[test]
+    fn beacon_committees_match_spec() {
+        let epoch = 200000;
+        let seed = seed(8);
+        // every third validator is inactive
+        let active_validator_indices: Vec<usize> = (0..3 * 5000).filter(|i| i % 3 != 0).collect();
+        let n = active_validator_indices.len();
+        let committees = get_beacon_committees(epoch, &active_validator_indices, &seed);
+
+        let committees_per_slot = get_committee_count_per_slot(n);
+        assert_eq!(committees_per_slot, 2);
+        assert_eq!(committees.len(), committees_per_slot * utils::SLOTS_PER_EPOCH);
+        for (i, committee) in committees.iter().enumerate() {
+            // spec: `compute_committee` with the index of the committee in the epoch
+            let count = committees_per_slot * utils::SLOTS_PER_EPOCH;
+            let expected: Vec<usize> = (n * i / count..n * (i + 1) / count)
+                .map(|j| active_validator_indices[spec_compute_shuffled_index(j, n, &seed)])
+                .collect();
+            assert_eq!(committee.slot, utils::epoch_to_slot(epoch) + i / committees_per_slot);
+            assert_eq!(committee.index, i % committees_per_slot);
+            assert_eq!(committee.validators, expected);
+        }
+    }
+
+    #[test]
+    fn committee_count_per_slot() {
+        assert_eq!(get_committee_count_per_slot(0), 1);
+        assert_eq!(get_committee_count_per_slot(32 * 128 * 2 - 1), 1);
+        assert_eq!(get_committee_count_per_slot(32 * 128 * 2), 2);
+        assert_eq!(get_committee_count_per_slot(1_000_000), 64);
+    }
+
+    /// Value of a `key: value` entry of a flat YAML mapping (block or flow style)
+    fn yaml_value<'a>(yaml: &'a str, key: &str) -> &'a str {
+        let start = yaml.find(&format!("{}:", key)).unwrap_or_else(|| panic!("{} not found", key)) + key.len() + 1;
+        let value = yaml[start..].trim_start();
+        let end = match value.strip_prefix('[') {
+            Some(list) => list.find(']').unwrap() + 2,
+            None => value.find([',', '\n', '}']).unwrap_or(value.len()),
+        };
+        value[..end].trim().trim_matches('\'').trim_matches('"')
+    }
+
+    /// Shuffling test vectors of the consensus specs (`tests/*/phase0/shuffling/core/shuffle/*/mapping.yaml`
+    /// of the consensus-spec-tests release archives, extracted to `CONSENSUS_SPEC_TESTS_DIR`)
+    #[test]
+    #[ignore = "needs the consensus spec test vectors in CONSENSUS_SPEC_TESTS_DIR"]
+    fn shuffled_indices_match_spec_vectors() {
+        let dir = std::env::var("CONSENSUS_SPEC_TESTS_DIR").expect("CONSENSUS_SPEC_TESTS_DIR not set");
+        let mut num_vectors = 0;
+        for preset in ["mainnet", "minimal"] {
+            let cases = std::path::Path::new(&dir).join(format!("tests/{}/phase0/shuffling/core/shuffle", preset));
+            let Ok(cases) = std::fs::read_dir(cases) else { continue };
+            for case in cases {
+                let yaml = std::fs::read_to_string(case.unwrap().path().join("mapping.yaml")).unwrap();
+                let seed = bytes32_from_0xhex(yaml_value(&yaml, "seed")).unwrap();
+                let count: usize = yaml_value(&yaml, "count").parse().unwrap();
+                let mapping: Vec<usize> = yaml_value(&yaml, "mapping")
+                    .trim_matches(['[', ']'])
+                    .split(',')
+                    .filter(|index| !index.trim().is_empty())
+                    .map(|index| index.trim().parse().unwrap())
+                    .collect();
+                assert_eq!(compute_shuffled_indices(count, &seed), mapping, "seed {:?} count {}", seed, count);
+                num_vectors += 1;
+            }
+        }
+        assert!(num_vectors > 0, "No shuffling test vectors found in {}", dir);
+    }
+}
@@ -0,0 +1,95 @@
+use rocksdb::{Direction, IteratorMode, DB, Options};
+
+use crate::utils;
+
+/// Slots and epoch states covered by the caching database
+pub struct Coverage {
+    pub synched_ranges: Vec<(usize, usize)>,        // inclusive ranges of synched slots
+    pub epochs_missing_state: Vec<(usize, usize)>,  // epoch and slot of its first synched block
+}
+
+impl Coverage {
+    /// Inclusive ranges of slots not synched between the synched ranges
+    pub fn gaps(&self) -> Vec<(usize, usize)> {
+        self.synched_ranges
+            .windows(2)
+            .map(|w| (w[0].1 + 1, w[1].0 - 1))
+            .collect()
+    }
+}
+
+/// Numbers `<n>` of the keys `<prefix><n><suffix>`, in ascending order
+fn get_key_numbers(db: &DB, prefix: &str, suffix: &str) -> Result<Vec<usize>, Box<dyn std::error::Error>> {
+    let mut numbers = vec![];
+    for item in db.iterator(IteratorMode::From(prefix.as_bytes(), Direction::Forward)) {
+        let (key, _) = item?;
+        let key = String::from_utf8_lossy(&key);
+        let number = match key.strip_prefix(prefix) {
+            Some(rest) => rest.strip_suffix(suffix).and_then(|n| n.parse::<usize>().ok()),
+            None => break,
+        };
+        if let Some(number) = number {
+            numbers.push(number);
+        }
+    }
+    // keys are ordered lexicographically
+    numbers.sort_unstable();
+    Ok(numbers)
+}
+
+pub fn get_coverage(db: &DB) -> Result<Coverage, Box<dyn std::error::Error>> {
+    let synched_slots = get_key_numbers(db, "slot_", "_synched")?;
+    let synched_epochs = get_key_numbers(db, "epoch_", "_state_synched")?;
+
+    let mut synched_ranges: Vec<(usize, usize)> = vec![];
+    for slot in &synched_slots {
+        match synched_ranges.last_mut() {
+            Some((_, last)) if *last + 1 == *slot => *last = *slot,
+            _ => synched_ranges.push((*slot, *slot)),
+        }
+    }
+
+    // state data is synched at the first block of every epoch
+    let mut epochs_missing_state = vec![];
+    let mut last_epoch_with_block = None;
+    for slot in &synched_slots {
+        let epoch = utils::slot_to_epoch(*slot);
+        if last_epoch_with_block == Some(epoch) || db.get(format!("block_{}", slot))?.is_none() {
+            continue;
+        }
+        last_epoch_with_block = Some(epoch);
+        if synched_epochs.binary_search(&epoch).is_err() {
+            epochs_missing_state.push((epoch, *slot));
+        }
+    }
+
+    Ok(Coverage { synched_ranges, epochs_missing_state })
+}
+
+pub async fn main(db_path: String) -> Result<(), Box<dyn std::error::Error>> {
+    let mut db_opts = Options::default();
+    db_opts.increase_parallelism(utils::get_available_cpucores() as i32);
+    db_opts.optimize_for_point_lookup(utils::get_available_ram() as u64 / 4);
+    let db = DB::open_for_read_only(&db_opts, db_path, true)?;
+
+    let coverage = get_coverage(&db)?;
+    for (first, last) in &coverage.synched_ranges {
+        println!("SYNCHED slots={}..={} epochs={}..={}", first, last, utils::slot_to_epoch(*first), utils::slot_to_epoch(*last));
+    }
+    let gaps = coverage.gaps();
+    for (first, last) in &gaps {
+        println!("GAP slots={}..={} count={}", first, last, last - first + 1);
+    }
+    for (epoch, slot) in &coverage.epochs_missing_state {
+        println!("MISSING_STATE epoch={} slot={}", epoch, slot);
+    }
+    log::info!(
+        "{} synched ranges, {} gaps ({} slots), {} epochs missing state",
+        coverage.synched_ranges.len(),
+        gaps.len(),
+        gaps.iter().map(|(first, last)| last - first + 1).sum::<usize>(),
+        coverage.epochs_missing_state.len()
+    );
+
+    Ok(())
+}
@@ -1,24 +1,54 @@
 use bincode;
 use ratelimit::Ratelimiter;
-use reqwest;
 use rocksdb::{DB, Options};
+use std::collections::HashSet;
 
-mod api;
+pub mod api;
 use crate::data;
+use crate::metrics;
+use crate::shuffling;
+use crate::status;
 use crate::utils::{self, is_epoch_boundary_slot};
 
+/// Where the committees of epochs are obtained from
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum CommitteeSource {
+    /// Committees endpoint of the beacon chain RPC (requires historical states on the node)
+    Rpc,
+    /// Computed locally from the validator registry and RANDAO mixes of a single state
+    Local,
+    /// Computed locally and compared with the committees endpoint of the beacon chain RPC
+    Verify,
+}
+
+/// How to obtain the committees of epochs
+pub struct CommitteeOptions {
+    pub source: CommitteeSource,
+    pub state_id: String,   // state providing validator registry and RANDAO mixes
+}
+
+/// Which slots to synchronize
+pub struct SlotRangeOptions {
+    pub min_slot: usize,
+    pub max_slot: Option<usize>,
+    pub fill_gaps: bool,    // only slots in gaps between synched slots and epochs missing state
+    pub genesis_time: u64,  // to restrict the range to slots in the past
+}
+
 fn ratelimiter_wait(ratelimiter: &mut Ratelimiter) {
     while let Err(sleep) = ratelimiter.try_wait() {
+        metrics::RATELIMITER_WAIT_SECONDS.inc_by(sleep.as_secs_f64());
         std::thread::sleep(sleep);
     }
 }
 
 pub async fn main(
     db_path: String,
-    rpc_url: String,
-    min_slot: usize,
-    max_slot: usize,
+    rpc_urls: Vec<String>,
+    client_options: api::ClientOptions,
+    slot_range: SlotRangeOptions,
     mut ratelimiter: Ratelimiter,
+    committee_options: CommitteeOptions,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let mut db_opts = Options::default();
     db_opts.create_if_missing(true);
@@ -26,7 +56,33 @@ pub async fn main(
     db_opts.optimize_level_style_compaction(utils::get_available_ram() / 4);
     db_opts.optimize_for_point_lookup(utils::get_available_ram() as u64 / 4);
     let db = DB::open(&db_opts, db_path)?;
-    let mut rpc = reqwest::Client::new();
+    let mut rpc = api::ApiClient::new(rpc_urls, &client_options)?;
+
+    // restrict sync to the gaps between synched ranges, and resync the first blocks of epochs missing state
+    let mut resync_slots = HashSet::new();
+    let (min_slot, max_slot) = if slot_range.fill_gaps {
+        let coverage = status::get_coverage(&db)?;
+        let (first_synched, last_synched) = match (coverage.synched_ranges.first(), coverage.synched_ranges.last()) {
+            (Some((first, _)), Some((_, last))) => (*first, *last),
+            _ => return Err("No synched slots in caching database, no gaps to fill".into()),
+        };
+        let gaps = coverage.gaps();
+        log::info!(
+            "Filling {} gaps ({} slots) and {} epochs missing state between slots {} and {}",
+            gaps.len(),
+            gaps.iter().map(|(first, last)| last - first + 1).sum::<usize>(),
+            coverage.epochs_missing_state.len(),
+            first_synched,
+            last_synched
+        );
+        resync_slots.extend(coverage.epochs_missing_state.iter().map(|(_, slot)| *slot));
+        (
+            std::cmp::max(slot_range.min_slot, first_synched),
+            std::cmp::min(slot_range.max_slot.unwrap_or(last_synched), last_synched),
+        )
+    } else {
+        (slot_range.min_slot, slot_range.max_slot.ok_or("Maximum slot is required unless filling gaps")?)
+    };
 
     // ensure sync is up to a reasonable target
     if max_slot < min_slot {
@@ -36,7 +92,7 @@ pub async fn main(
     }
     let mut max_slot = max_slot;
     let now_unixtime = utils::get_unixtime();
-    let now_slot = utils::unixtime_to_slot(now_unixtime);
+    let now_slot = utils::unixtime_to_slot(now_unixtime, slot_range.genesis_time);
     if max_slot > now_slot - utils::GAP_LATEST_SLOT_NOW_SLOT_CANONICAL_CHAIN_STABILITY {
         let new_max_slot = now_slot - utils::GAP_LATEST_SLOT_NOW_SLOT_CANONICAL_CHAIN_STABILITY;
         log::warn!(
@@ -69,11 +125,38 @@ pub async fn main(
     max_slot += 1; // include last epoch boundary block in sync
     log::info!("Syncing slots {}..{}", min_slot, max_slot);
 
+    // load validator registry once to compute committees locally
+    let validator_epochs = match committee_options.source {
+        CommitteeSource::Rpc => None,
+        CommitteeSource::Local | CommitteeSource::Verify => {
+            ratelimiter_wait(&mut ratelimiter);
+            let validators = api::get_state_validators(&mut rpc, &committee_options.state_id).await?;
+            log::info!(
+                "Loaded registry of {} validators from state {}",
+                validators.len(),
+                committee_options.state_id
+            );
+            let mut validator_epochs = vec![(0, 0); validators.len()];
+            for validator in validators {
+                if validator.index >= validator_epochs.len() {
+                    return Err(format!("Validator registry of state {} is incomplete", committee_options.state_id).into());
+                }
+                validator_epochs[validator.index] = (validator.validator.activation_epoch, validator.validator.exit_epoch);
+            }
+            Some(validator_epochs)
+        }
+    };
+
     let mut last_block_root: Option<data::Root> = None;
     // sync
     for slot in min_slot..max_slot {
-        if db.get(format!("slot_{}_synched", slot))?.is_some() {
+        if db.get(format!("slot_{}_synched", slot))?.is_some() && !resync_slots.contains(&slot) {
             log::info!("Skipping synched slot {}", slot);
+            // keep track of the latest block for epoch boundary blocks at empty slots after skipped slots
+            if let Some(root) = db.get(format!("block_{}", slot))? {
+                last_block_root = Some(bincode::deserialize(&root)?);
+            }
+            metrics::SYNC_LAST_SYNCED_SLOT.set(slot as i64);
             continue;
         } else {
             log::info!("Syncing slot {}", slot);
@@ -81,7 +164,7 @@ pub async fn main(
 
         // sync canonical chain blocks
         ratelimiter_wait(&mut ratelimiter);
-        let blk_root = match api::get_blockroot_by_slot(&mut rpc, &rpc_url, &slot).await? {
+        let blk_root = match api::get_blockroot_by_slot(&mut rpc, &slot).await? {
             Some(root) => {
                 log::debug!("Canonical block root: {:?}", &root);
                 db.put(format!("block_{}", &slot), bincode::serialize(&root)?)?;
@@ -101,22 +184,27 @@ pub async fn main(
                     format!("slot_{}_synched", slot),
                     bincode::serialize(&true)?,
                     )?;
+                metrics::SYNC_LAST_SYNCED_SLOT.set(slot as i64);
                 continue;
             }, // skip empty slots
         };
 
         // sync block
         ratelimiter_wait(&mut ratelimiter);
-        let blk = api::get_block_by_blockroot(&mut rpc, &rpc_url, &blk_root)
+        let (blk, execution_payload) = api::get_block_by_blockroot(&mut rpc, &blk_root)
             .await?
             .expect("Block not found");
         log::debug!("Canonical block: {:?}", &blk);
         db.put(format!("block_{}", &blk_root), bincode::serialize(&blk)?)?;
+        if let Some(execution_payload) = execution_payload {
+            log::debug!("Execution block: {:?}", &execution_payload);
+            db.put(format!("block_{}_execution_payload", &blk_root), bincode::serialize(&execution_payload)?)?;
+        }
 
         // sync state at epoch boundaries or at the first blocks of epochs
         if db.get(format!("epoch_{}_state_synched", &utils::slot_to_epoch(slot)))?.is_none() {
             ratelimiter_wait(&mut ratelimiter);
-            let tmp_state_root = api::get_stateroot_by_slot(&mut rpc, &rpc_url, &slot).await?;
+            let tmp_state_root = api::get_stateroot_by_slot(&mut rpc, &slot).await?;
             log::debug!(
                 "State-root by block: {:?} / state-root by slot: {:?}",
                 &blk.state_root,
@@ -126,7 +214,7 @@ pub async fn main(
 
             ratelimiter_wait(&mut ratelimiter);
             let (cp_previous_justified, cp_current_justified, cp_finalized) =
-                api::get_state_finality_checkpoints_by_slot(&mut rpc, &rpc_url, &slot).await?;
+                api::get_state_finality_checkpoints_by_slot(&mut rpc, &slot).await?;
             log::debug!(
                 "Finality checkpoints: {:?}, {:?}, {:?}",
                 &cp_previous_justified,
@@ -138,8 +226,40 @@ pub async fn main(
                 bincode::serialize(&(cp_previous_justified, cp_current_justified, cp_finalized))?,
             )?;
 
-            ratelimiter_wait(&mut ratelimiter);
-            let committees = api::get_state_committees_by_slot(&mut rpc, &rpc_url, &slot).await?;
+            let committees = match &validator_epochs {
+                None => {
+                    ratelimiter_wait(&mut ratelimiter);
+                    api::get_state_committees_by_slot(&mut rpc, &slot).await?
+                }
+                Some(validator_epochs) => {
+                    let epoch = utils::slot_to_epoch(slot);
+                    let seed_epoch = epoch
+                        .checked_sub(shuffling::MIN_SEED_LOOKAHEAD + 1)
+                        .ok_or_else(|| format!("Committees of epoch {} cannot be computed locally", epoch))?;
+                    ratelimiter_wait(&mut ratelimiter);
+                    let randao_mix = api::get_state_randao(&mut rpc, &committee_options.state_id, &seed_epoch).await?;
+                    let committees = shuffling::get_beacon_committees(
+                        epoch,
+                        &shuffling::get_active_validator_indices(validator_epochs, epoch),
+                        &shuffling::get_attester_seed(epoch, &shuffling::bytes32_from_0xhex(&randao_mix)?),
+                    );
+
+                    if committee_options.source == CommitteeSource::Verify {
+                        ratelimiter_wait(&mut ratelimiter);
+                        let mut rpc_committees = api::get_state_committees_by_slot(&mut rpc, &slot).await?;
+                        rpc_committees.sort_by_key(|c| (c.slot, c.index));
+                        if rpc_committees != committees {
+                            return Err(format!(
+                                "Committees of epoch {} computed locally differ from beacon chain RPC endpoint",
+                                epoch
+                            )
+                            .into());
+                        }
+                        log::debug!("Committees of epoch {} verified", epoch);
+                    }
+                    committees
+                }
+            };
             log::debug!("Committees: {:?}", &committees);
             db.put(
                 format!("state_{}_committees", blk.state_root),
@@ -147,7 +267,7 @@ pub async fn main(
             )?;
 
             ratelimiter_wait(&mut ratelimiter);
-            let tmp_state_root = api::get_stateroot_by_slot(&mut rpc, &rpc_url, &slot).await?;
+            let tmp_state_root = api::get_stateroot_by_slot(&mut rpc, &slot).await?;
             log::debug!(
                 "State-root by block: {:?} / state-root by slot: {:?}",
                 &blk.state_root,
@@ -162,6 +282,7 @@ pub async fn main(
             format!("slot_{}_synched", slot),
             bincode::serialize(&true)?,
             )?;
+        metrics::SYNC_LAST_SYNCED_SLOT.set(slot as i64);
     }
 
     Ok(())
@@ -0,0 +1,228 @@
+//! Synthetic caching databases for tests
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rocksdb::{DB, Options};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::confrule::{ExclusionMode, FfgTarget, QuorumBase, Rule, RuleOptions};
+use crate::data;
+use crate::utils;
+
+const COMMITTEES_PER_SLOT: usize = 2;
+const COMMITTEE_SIZE: usize = 16;
+pub const NUM_VALIDATORS: usize = COMMITTEES_PER_SLOT * COMMITTEE_SIZE * 32;
+
+/// Options of the `flexible-ffg` rule with the given quorums (finalized target, committee
+/// quorum base, no excluded validators), to adjust with struct update syntax
+pub fn rule_options(quorums: &[f64]) -> RuleOptions {
+    RuleOptions {
+        rule: Rule::FlexibleFfg,
+        quorums: quorums.to_vec(),
+        ffg_target: FfgTarget::Finalized,
+        quorum_base: QuorumBase::Committee,
+        active_window_epochs: 2,
+        excluded_validators: std::collections::BTreeSet::new(),
+        exclusion_mode: ExclusionMode::Offline,
+    }
+}
+
+/// Temporary file or directory path, removed when dropped
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("flexibleeth-test-{}-{}", name, std::process::id()));
+        remove_path(&path);
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn path_string(&self) -> String {
+        self.0.to_str().unwrap().to_string()
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        remove_path(&self.0);
+    }
+}
+
+/// Caching database in a temporary location, removed when dropped
+pub struct TempDb {
+    db: Option<DB>,
+    _path: TempPath,
+}
+
+impl TempDb {
+    pub fn new(name: &str) -> Self {
+        Self::open(TempPath::new(name))
+    }
+
+    /// Opens (or creates) the database at a temporary path, which is removed when dropped
+    pub fn open(path: TempPath) -> Self {
+        let mut db_opts = Options::default();
+        db_opts.create_if_missing(true);
+        let db = DB::open(&db_opts, path.path()).unwrap();
+        Self { db: Some(db), _path: path }
+    }
+
+    pub fn db(&self) -> &DB {
+        self.db.as_ref().unwrap()
+    }
+
+    pub fn put<T: serde::Serialize>(&self, key: &str, value: &T) {
+        self.db().put(key, bincode::serialize(value).unwrap()).unwrap();
+    }
+}
+
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        // closed before the path is removed
+        drop(self.db.take());
+    }
+}
+
+fn remove_path(path: &Path) {
+    let _ = std::fs::remove_dir_all(path);
+    let _ = std::fs::remove_file(path);
+}
+
+fn root(tag: &str, x: usize) -> data::Root {
+    format!("0x{:02x}{:062x}", tag.as_bytes()[0], x)
+}
+
+/// SSZ bitlist of the given bits (with length delimiter) as 0x-prefixed hex
+pub fn bitlist(bits: &[bool]) -> String {
+    let mut bytes = vec![0u8; (bits.len() + 1).div_ceil(8)];
+    for (i, bit) in bits.iter().enumerate() {
+        if *bit {
+            bytes[i / 8] |= 1 << (i % 8);
+        }
+    }
+    bytes[bits.len() / 8] |= 1 << (bits.len() % 8);
+    format!("0x{}", hex::encode(bytes))
+}
+
+/// Writes a synthetic canonical chain of slots `0..num_slots` to the database: 10% empty slots,
+/// every fourth validator offline, the others voting with probability 0.9 (for the epoch
+/// boundary block and the block of the slot), and votes included with random delays. Blocks carry
+/// execution block `1000 + slot`.
+pub fn write_chain(db: &TempDb, num_slots: usize, seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let num_epochs = num_slots.div_ceil(32);
+
+    let mut blocks: HashMap<usize, data::Root> = HashMap::new();
+    let mut ebbs: Vec<data::Root> = vec![];
+    let mut last = data::HEADER_GENESIS_ROOT.to_string();
+    for slot in 0..num_slots {
+        if slot == 0 {
+            blocks.insert(slot, last.clone());
+        } else if rng.gen_bool(0.9) {
+            last = root("b", slot);
+            blocks.insert(slot, last.clone());
+        }
+        if utils::is_epoch_boundary_slot(slot) {
+            ebbs.push(last.clone());
+        }
+    }
+    let mut heads: Vec<data::Root> = vec![];
+    let mut head = data::HEADER_GENESIS_ROOT.to_string();
+    for slot in 0..num_slots {
+        if let Some(blkroot) = blocks.get(&slot) {
+            head = blkroot.clone();
+        }
+        heads.push(head.clone());
+    }
+
+    let committees: Vec<Vec<data::CommitteeAssignment>> = (0..num_epochs)
+        .map(|epoch| {
+            let mut validators: Vec<usize> = (0..NUM_VALIDATORS).collect();
+            validators.shuffle(&mut rng);
+            let mut committees = vec![];
+            for slot in utils::epoch_to_slot(epoch)..utils::epoch_to_slot(epoch + 1) {
+                for index in 0..COMMITTEES_PER_SLOT {
+                    let first = ((slot % 32) * COMMITTEES_PER_SLOT + index) * COMMITTEE_SIZE;
+                    committees.push(data::CommitteeAssignment {
+                        index,
+                        slot,
+                        validators: validators[first..first + COMMITTEE_SIZE].to_vec(),
+                    });
+                }
+            }
+            committees
+        })
+        .collect();
+
+    let mut pending: Vec<data::Attestation> = vec![];
+    let mut parent_root = "0x0000000000000000000000000000000000000000000000000000000000000000".to_string();
+    for slot in 0..num_slots {
+        let epoch = utils::slot_to_epoch(slot);
+        // votes of the committees of the previous slot
+        if slot > 0 {
+            let vote_epoch = utils::slot_to_epoch(slot - 1);
+            for committee in committees[vote_epoch].iter().filter(|c| c.slot == slot - 1) {
+                for _ in 0..2 {
+                    let bits: Vec<bool> = committee
+                        .validators
+                        .iter()
+                        .map(|validator| validator % 4 != 0 && rng.gen_bool(0.9))
+                        .collect();
+                    let source_epoch = vote_epoch.saturating_sub(1);
+                    pending.push(data::Attestation {
+                        aggregation_bits: bitlist(&bits),
+                        data: data::AttestationData {
+                            slot: committee.slot,
+                            index: committee.index,
+                            beacon_block_root: heads[committee.slot].clone(),
+                            source: data::Checkpoint { epoch: source_epoch, root: ebbs[source_epoch].clone() },
+                            target: data::Checkpoint { epoch: vote_epoch, root: ebbs[vote_epoch].clone() },
+                        },
+                    });
+                }
+            }
+        }
+
+        if let Some(blkroot) = blocks.get(&slot) {
+            let (included, kept): (Vec<_>, Vec<_>) =
+                pending.drain(..).partition(|attestation| rng.gen_bool(0.7) || slot - attestation.data.slot > 40);
+            pending = kept;
+            let attestations = included.into_iter().filter(|attestation| slot - attestation.data.slot <= 64).collect();
+            let state_root = root("s", slot);
+            let blk = data::Block {
+                slot,
+                proposer_index: slot % 7,
+                parent_root: parent_root.clone(),
+                state_root: state_root.clone(),
+                body: data::BlockBody { attestations },
+            };
+            db.put(&format!("block_{}", slot), blkroot);
+            db.put(&format!("block_{}", blkroot), &blk);
+            db.put(
+                &format!("block_{}_execution_payload", blkroot),
+                &data::ExecutionPayload { block_number: 1000 + slot, block_hash: root("e", slot) },
+            );
+            if db.db().get(format!("epoch_{}_state_synched", epoch)).unwrap().is_none() {
+                let justified = data::Checkpoint { epoch: epoch.saturating_sub(1), root: ebbs[epoch.saturating_sub(1)].clone() };
+                let finalized = match epoch {
+                    0 | 1 => data::Checkpoint { epoch: 0, root: "0x0000000000000000000000000000000000000000000000000000000000000000".to_string() },
+                    _ => data::Checkpoint { epoch: epoch - 2, root: ebbs[epoch - 2].clone() },
+                };
+                db.put(&format!("state_{}_finality_checkpoints", state_root), &(justified.clone(), justified, finalized));
+                db.put(&format!("state_{}_committees", state_root), &committees[epoch]);
+                db.put(&format!("epoch_{}_state_synched", epoch), &true);
+            }
+            parent_root = blkroot.clone();
+        }
+        if utils::is_epoch_boundary_slot(slot) {
+            db.put(&format!("ebb_{}_root", epoch), &ebbs[epoch]);
+        }
+        db.put(&format!("slot_{}_synched", slot), &true);
+    }
+}
@@ -1,9 +1,11 @@
+use serde::{Deserialize, Serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
 use libc;
 use num_cpus;
 
 pub const SLOTS_PER_EPOCH: usize = 32;
 pub const SECONDS_PER_SLOT: usize = 12;
+pub const GENESIS_TIME: u64 = 1606824023; // mainnet
 
 pub const GAP_LATEST_SLOT_NOW_SLOT_CANONICAL_CHAIN_STABILITY: usize = 5 * SLOTS_PER_EPOCH;
 
@@ -23,8 +25,12 @@ pub fn is_epoch_boundary_slot(slot: usize) -> bool {
     slot % SLOTS_PER_EPOCH == 0
 }
 
-pub fn unixtime_to_slot(unixtime: u64) -> usize {
-    unixtime as usize / SECONDS_PER_SLOT
+pub fn unixtime_to_slot(unixtime: u64, genesis_time: u64) -> usize {
+    unixtime.saturating_sub(genesis_time) as usize / SECONDS_PER_SLOT
+}
+
+pub fn slot_to_unixtime(slot: usize, genesis_time: u64) -> u64 {
+    genesis_time + (slot * SECONDS_PER_SLOT) as u64
 }
 
 pub fn get_unixtime() -> u64 {
@@ -51,7 +57,7 @@ pub fn is_consistent_with<T: PartialEq>(vec1: &[T], vec2: &[T]) -> bool {
     is_prefix_of(vec1, vec2) || is_prefix_of(vec2, vec1)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct AggregationBits {
     bits: Vec<u8>,
 }
@@ -88,6 +94,36 @@ impl AggregationBits {
         delta
     }
 
+    /// Clears the bits at the given positions (positions past the bits are ignored)
+    pub fn clear_positions(&mut self, positions: &[usize]) {
+        for position in positions {
+            if let Some(val) = self.bits.get_mut(position / 8) {
+                *val &= !(1 << (position % 8));
+            }
+        }
+    }
+
+    /// Positions of the set bits (bit `i` is bit `i % 8` of byte `i / 8`)
+    pub fn get_set_positions(&self) -> Vec<usize> {
+        let mut positions = vec![];
+        for (i, val) in self.bits.iter().enumerate() {
+            for j in 0..8 {
+                if val & (1 << j) != 0 {
+                    positions.push(i * 8 + j);
+                }
+            }
+        }
+        positions
+    }
+
+    /// Positions of the votes, i.e., of the set bits except for the highest one, which is the
+    /// length delimiter of the SSZ bitlist
+    pub fn get_vote_positions(&self) -> Vec<usize> {
+        let mut positions = self.get_set_positions();
+        positions.pop();
+        positions
+    }
+
     pub fn count(&self) -> usize {
         let mut cnt = 0;
         for val in &self.bits {
@@ -107,3 +143,17 @@ pub fn get_available_ram() -> usize {
 pub fn get_available_cpucores() -> usize {
     num_cpus::get()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unixtime_to_slot_is_relative_to_genesis() {
+        assert_eq!(unixtime_to_slot(GENESIS_TIME, GENESIS_TIME), 0);
+        assert_eq!(unixtime_to_slot(GENESIS_TIME + 11, GENESIS_TIME), 0);
+        assert_eq!(unixtime_to_slot(GENESIS_TIME + 12 * 6398000, GENESIS_TIME), 6398000);
+        assert_eq!(unixtime_to_slot(GENESIS_TIME - 1, GENESIS_TIME), 0);
+        assert_eq!(unixtime_to_slot(slot_to_unixtime(1234, 1000), 1000), 1234);
+    }
+}